@@ -0,0 +1,27 @@
+use bevy::prelude::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, SeedableRng};
+use rusty_jam::{mapgen::Map, room::new_rooms};
+
+/// Every run builds the same maps, so runs can be compared.
+const SEED: u64 = 0;
+
+fn generate(c: &mut Criterion) {
+    let rooms = new_rooms(|_| Handle::default());
+
+    let mut group = c.benchmark_group("mapgen");
+    group.sample_size(20);
+    for &size in &[20, 50, 100, 200] {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter(|| {
+                let mut map = Map::new(size, size);
+                map.generate(&rooms, &mut StdRng::seed_from_u64(SEED));
+                map
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, generate);
+criterion_main!(benches);
@@ -0,0 +1,465 @@
+use crate::door::LockedDoors;
+use crate::input::{Action, ActionState};
+use crate::intruder::*;
+use crate::mapgen::Map;
+use crate::room::*;
+use crate::security_camera::SecurityCamera;
+use crate::vision::*;
+use crate::{Player, TIMESTEP};
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How far the guard can see, and spot an intruder from.
+pub const SIGHT_RANGE: f32 = 4. * TILE_SIZE;
+/// How close the guard has to be for an intruder they can see to feel chased.
+const CHASE_RANGE: f32 = 2. * TILE_SIZE;
+/// How many tiles away an intruder will look for somewhere to hide.
+const HIDE_SEARCH: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BehaviourState {
+    Sneaking,
+    Stealing,
+    Hiding,
+    Fleeing,
+    Rerouting,
+}
+
+/// Things that can push an intruder into another state, highest priority first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Chased,
+    Seen,
+    Heard,
+    PathBlocked,
+    ReachedTarget,
+    TimerDone,
+}
+
+pub struct Transition {
+    pub from: BehaviourState,
+    pub trigger: Trigger,
+    pub to: BehaviourState,
+}
+
+/// The intruder state machine: which trigger moves which state where, and how long timed states
+/// last in seconds.
+pub struct BehaviourTable {
+    pub transitions: Vec<Transition>,
+    pub durations: HashMap<BehaviourState, f32>,
+}
+
+impl Default for BehaviourTable {
+    fn default() -> Self {
+        use BehaviourState::*;
+        use Trigger::*;
+        let transitions = vec![
+            (Sneaking, Chased, Fleeing),
+            (Sneaking, Seen, Hiding),
+            (Sneaking, Heard, Hiding),
+            (Sneaking, PathBlocked, Rerouting),
+            (Sneaking, ReachedTarget, Stealing),
+            (Stealing, Chased, Fleeing),
+            (Stealing, Seen, Hiding),
+            (Stealing, Heard, Hiding),
+            (Stealing, TimerDone, Sneaking),
+            (Hiding, Chased, Fleeing),
+            (Hiding, TimerDone, Sneaking),
+            (Fleeing, TimerDone, Hiding),
+            (Rerouting, Chased, Fleeing),
+            (Rerouting, Seen, Hiding),
+            (Rerouting, Heard, Hiding),
+            (Rerouting, TimerDone, Sneaking),
+        ]
+        .into_iter()
+        .map(|(from, trigger, to)| Transition { from, trigger, to })
+        .collect();
+
+        let mut durations = HashMap::new();
+        durations.insert(Stealing, 3.);
+        durations.insert(Hiding, 4.);
+        durations.insert(Fleeing, 5.);
+        durations.insert(Rerouting, 1.);
+
+        Self {
+            transitions,
+            durations,
+        }
+    }
+}
+
+impl BehaviourTable {
+    /// The default table for intruders `skill` times as practised as a first night's. They get
+    /// through stealing, hiding and fleeing that much quicker.
+    pub fn with_skill(skill: f32) -> Self {
+        let mut table = Self::default();
+        for duration in table.durations.values_mut() {
+            *duration /= skill;
+        }
+        table
+    }
+
+    pub fn next(&self, from: BehaviourState, trigger: Trigger) -> Option<BehaviourState> {
+        self.transitions
+            .iter()
+            .find(|t| t.from == from && t.trigger == trigger)
+            .map(|t| t.to)
+    }
+
+    /// The state reached by the highest priority trigger that has a transition out of `from`.
+    pub fn step(&self, from: BehaviourState, triggers: &[Trigger]) -> Option<BehaviourState> {
+        triggers
+            .iter()
+            .find_map(|&trigger| self.next(from, trigger))
+    }
+}
+
+pub struct Behaviour {
+    pub state: BehaviourState,
+    timer: Option<Timer>,
+}
+
+impl Default for Behaviour {
+    fn default() -> Self {
+        Self {
+            state: BehaviourState::Sneaking,
+            timer: None,
+        }
+    }
+}
+
+impl Behaviour {
+    pub fn enter(&mut self, state: BehaviourState, table: &BehaviourTable) {
+        self.state = state;
+        self.timer = table
+            .durations
+            .get(&state)
+            .map(|&secs| Timer::from_seconds(secs, false));
+    }
+
+    /// Advance the state timer, returning whether it just ran out.
+    pub fn tick(&mut self, delta: Duration) -> bool {
+        match &mut self.timer {
+            Some(timer) => timer.tick(delta).just_finished(),
+            None => false,
+        }
+    }
+
+    /// How fast the intruder moves in this state, relative to its normal speed.
+    pub fn speed_factor(&self) -> f32 {
+        match self.state {
+            BehaviourState::Fleeing => 1.6,
+            _ => 1.,
+        }
+    }
+}
+
+/// What an intruder currently knows about the guard.
+#[derive(Default)]
+pub struct Senses {
+    pub seen: bool,
+    /// Seen this tick but not the one before.
+    pub spotted: bool,
+    pub chased: bool,
+    /// Where the guard or camera that saw the intruder is.
+    pub threat: Option<Vec2>,
+    /// Where the last suspicious sound came from, until the intruder stops hiding from it.
+    pub heard: Option<Vec2>,
+    /// A sound was heard since the behaviour last updated.
+    pub new_sound: bool,
+}
+
+pub struct IntruderStole {
+    pub intruder: Entity,
+    pub tile: (usize, usize),
+}
+
+pub fn sense_threats(
+    occluders: Res<Occluders>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    camera_query: Query<(&GlobalTransform, &SecurityCamera)>,
+    mut intruder_query: Query<(&GlobalTransform, &mut Senses), With<Intruder>>,
+) {
+    let player = match player_query.single() {
+        Ok(p) => p.translation.truncate(),
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    for (tran, mut senses) in intruder_query.iter_mut() {
+        let position = tran.translation.truncate();
+        let camera = camera_query
+            .iter()
+            .map(|(cam_tran, camera)| (cam_tran.translation.truncate(), camera))
+            .find(|(origin, camera)| camera.cone().can_see(*origin, position, &occluders))
+            .map(|(origin, _)| origin);
+
+        // the guard has no facing, so they see all around them
+        let guard_sees = VisionCone::all_around(SIGHT_RANGE).can_see(player, position, &occluders);
+        let threat = if guard_sees { Some(player) } else { camera };
+        let seen = threat.is_some();
+
+        senses.spotted = seen && !senses.seen;
+        senses.seen = seen;
+        senses.chased = guard_sees && position.distance(player) < CHASE_RANGE;
+        senses.threat = threat;
+    }
+}
+
+pub fn update_behaviour(
+    table: Res<BehaviourTable>,
+    map: Res<Map>,
+    locked: Res<LockedDoors>,
+    mut query: Query<(
+        Entity,
+        &GlobalTransform,
+        &mut Senses,
+        &mut Behaviour,
+        &mut IntruderPath,
+    )>,
+    mut stole: EventWriter<IntruderStole>,
+) {
+    for (entity, tran, mut senses, mut behaviour, mut path) in query.iter_mut() {
+        let timer_done = behaviour.tick(Duration::from_secs_f64(TIMESTEP));
+        let here = map.world_to_tile(tran.translation.x, tran.translation.y);
+
+        let mut triggers = Vec::new();
+        if senses.chased {
+            triggers.push(Trigger::Chased);
+        }
+        if senses.spotted {
+            triggers.push(Trigger::Seen);
+        }
+        if senses.new_sound {
+            senses.new_sound = false;
+            triggers.push(Trigger::Heard);
+        }
+        if path.blocked(&map, &locked) {
+            triggers.push(Trigger::PathBlocked);
+        }
+        if path.waypoints.is_empty() && path.target.is_some() && path.target == here {
+            triggers.push(Trigger::ReachedTarget);
+        }
+        if timer_done {
+            triggers.push(Trigger::TimerDone);
+        }
+
+        let next = match table.step(behaviour.state, &triggers) {
+            Some(next) => next,
+            None => continue,
+        };
+
+        // finished the job, go find another
+        if behaviour.state == BehaviourState::Stealing && next == BehaviourState::Sneaking {
+            if let Some(tile) = path.target.take() {
+                stole.send(IntruderStole {
+                    intruder: entity,
+                    tile,
+                });
+            }
+        }
+
+        // back to work, whatever it heard is forgotten
+        if next == BehaviourState::Sneaking {
+            senses.heard = None;
+        }
+
+        // every state plans its own movement
+        path.waypoints.clear();
+        behaviour.enter(next, &table);
+    }
+}
+
+/// Move hiding intruders away from the guard (or what they heard), fleeing intruders back to the
+/// entrance and rerouting intruders around whatever got locked in their way.
+pub fn act_on_behaviour(
+    map: Res<Map>,
+    locked: Res<LockedDoors>,
+    mut query: Query<(&GlobalTransform, &Senses, &Behaviour, &mut IntruderPath)>,
+) {
+    for (tran, senses, behaviour, mut path) in query.iter_mut() {
+        if !path.waypoints.is_empty() {
+            continue;
+        }
+
+        let position = tran.translation.truncate();
+        let here = match map.world_to_tile(position.x, position.y) {
+            Some(here) => here,
+            None => continue,
+        };
+        let is_locked = |id| locked.0.contains(&id);
+
+        let destination = match behaviour.state {
+            BehaviourState::Hiding => {
+                let threat = match senses.threat.or(senses.heard) {
+                    Some(threat) => threat,
+                    None => continue,
+                };
+                map.reachable(here, HIDE_SEARCH, is_locked)
+                    .into_iter()
+                    .map(|(tile, _)| tile)
+                    .max_by(|&a, &b| {
+                        let a = tile_to_world(a.0, a.1).distance(threat);
+                        let b = tile_to_world(b.0, b.1).distance(threat);
+                        a.partial_cmp(&b).unwrap()
+                    })
+            }
+            BehaviourState::Fleeing => map.entrance(),
+            BehaviourState::Rerouting => path.target,
+            _ => None,
+        };
+
+        if let Some(destination) = destination {
+            if destination != here {
+                match plan_path(&map, position, destination, &locked) {
+                    Some(waypoints) => path.waypoints = waypoints,
+                    // locked out of the target, pick another once back to sneaking
+                    None if behaviour.state == BehaviourState::Rerouting => path.target = None,
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
+/// Shows every intruder's current state above it. F2 toggles.
+#[derive(Default)]
+pub struct BehaviourDebug(pub bool);
+
+struct BehaviourLabel;
+
+pub fn attach_behaviour_labels(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    query: Query<Entity, Added<Behaviour>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).with_children(|parent| {
+            parent
+                .spawn_bundle(Text2dBundle {
+                    text: Text::with_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+                            font_size: 14.,
+                            color: Color::WHITE,
+                        },
+                        TextAlignment {
+                            vertical: VerticalAlign::Center,
+                            horizontal: HorizontalAlign::Center,
+                        },
+                    ),
+                    transform: Transform::from_xyz(0., 16., 5.),
+                    ..Default::default()
+                })
+                .insert(BehaviourLabel);
+        });
+    }
+}
+
+pub fn update_behaviour_labels(
+    actions: Res<ActionState>,
+    mut debug: ResMut<BehaviourDebug>,
+    intruder_query: Query<(&Behaviour, &Children)>,
+    mut label_query: Query<&mut Text, With<BehaviourLabel>>,
+) {
+    if actions.just_pressed(Action::BehaviourDebug) {
+        debug.0 = !debug.0;
+    }
+
+    for (behaviour, children) in intruder_query.iter() {
+        for &child in children.iter() {
+            if let Ok(mut text) = label_query.get_mut(child) {
+                let value = if debug.0 {
+                    format!("{:?}", behaviour.state)
+                } else {
+                    String::new()
+                };
+                if text.sections[0].value != value {
+                    text.sections[0].value = value;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use BehaviourState::*;
+
+    const STATES: [BehaviourState; 5] = [Sneaking, Stealing, Hiding, Fleeing, Rerouting];
+
+    #[test]
+    fn transitions() {
+        let table = BehaviourTable::default();
+        assert_eq!(table.next(Sneaking, Trigger::Chased), Some(Fleeing));
+        assert_eq!(table.next(Sneaking, Trigger::Seen), Some(Hiding));
+        assert_eq!(table.next(Sneaking, Trigger::Heard), Some(Hiding));
+        assert_eq!(table.next(Sneaking, Trigger::PathBlocked), Some(Rerouting));
+        assert_eq!(table.next(Sneaking, Trigger::ReachedTarget), Some(Stealing));
+        assert_eq!(table.next(Stealing, Trigger::TimerDone), Some(Sneaking));
+        assert_eq!(table.next(Hiding, Trigger::TimerDone), Some(Sneaking));
+        assert_eq!(table.next(Fleeing, Trigger::TimerDone), Some(Hiding));
+        assert_eq!(table.next(Rerouting, Trigger::TimerDone), Some(Sneaking));
+        assert_eq!(table.next(Rerouting, Trigger::Heard), Some(Hiding));
+
+        // fleeing intruders don't stop for anything
+        for &trigger in &[Trigger::Chased, Trigger::Seen, Trigger::Heard] {
+            assert_eq!(table.next(Fleeing, trigger), None);
+        }
+        // nothing times out of sneaking, it has no timer
+        assert_eq!(table.next(Sneaking, Trigger::TimerDone), None);
+    }
+
+    #[test]
+    fn highest_priority_trigger_wins() {
+        let table = BehaviourTable::default();
+        let triggers = [Trigger::Chased, Trigger::Seen, Trigger::PathBlocked];
+        assert_eq!(table.step(Sneaking, &triggers), Some(Fleeing));
+        // hiding ignores being seen, but not being chased
+        assert_eq!(table.step(Hiding, &triggers[1..]), None);
+        assert_eq!(table.step(Hiding, &triggers), Some(Fleeing));
+        assert_eq!(table.step(Sneaking, &[]), None);
+    }
+
+    #[test]
+    fn every_timed_state_times_out() {
+        let table = BehaviourTable::default();
+        for &state in STATES.iter() {
+            let timed = table.durations.contains_key(&state);
+            let times_out = table.next(state, Trigger::TimerDone).is_some();
+            assert_eq!(timed, times_out, "{:?}", state);
+        }
+        assert_eq!(table.durations[&Stealing], 3.);
+        assert_eq!(table.durations[&Hiding], 4.);
+        assert_eq!(table.durations[&Fleeing], 5.);
+        assert_eq!(table.durations[&Rerouting], 1.);
+    }
+
+    #[test]
+    fn skilled_intruders_are_quicker() {
+        let table = BehaviourTable::default();
+        let skilled = BehaviourTable::with_skill(2.);
+        for (state, duration) in table.durations.iter() {
+            assert_eq!(skilled.durations[state], duration / 2.);
+        }
+        assert_eq!(skilled.transitions.len(), table.transitions.len());
+    }
+
+    #[test]
+    fn state_timer_runs_for_its_duration() {
+        let table = BehaviourTable::default();
+        let mut behaviour = Behaviour::default();
+        behaviour.enter(Hiding, &table);
+        assert!(!behaviour.tick(Duration::from_secs_f32(3.9)));
+        assert!(behaviour.tick(Duration::from_secs_f32(0.2)));
+
+        // untimed states never run out
+        behaviour.enter(Sneaking, &table);
+        assert!(!behaviour.tick(Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::input::{Action, ActionState};
+use crate::mapgen::Map;
+use crate::room::TILE_SIZE;
+use crate::trigger::{TriggerKind, TriggerOverlaps, TriggerVolume};
+use crate::{MainCamera, Player, TIMESTEP};
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+use bevy::render::camera::OrthographicProjection;
+
+/// Pixels of scrolling that count as one line, for touchpads.
+const PIXELS_PER_LINE: f32 = 16.;
+
+/// How the main camera follows the guard. `RoomCamera` switches to framing whole rooms, the mouse
+/// wheel zooms.
+pub struct CameraController {
+    /// How quickly the camera catches up, higher is snappier.
+    pub damping: f32,
+    /// Half size of the box around the middle of the screen the guard can move around in without
+    /// the camera following.
+    pub dead_zone: Vec2,
+    /// Projection scale limits. Smaller is closer in.
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    /// How much one line of scrolling zooms by.
+    pub zoom_step: f32,
+    /// Frame the room the guard is in instead of following them.
+    pub snap_to_room: bool,
+    /// Jump straight to the guard next tick instead of panning there.
+    pub cut: bool,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            damping: 6.,
+            dead_zone: Vec2::new(48., 32.),
+            min_zoom: 0.5,
+            max_zoom: 2.,
+            zoom_step: 0.1,
+            snap_to_room: false,
+            cut: true,
+        }
+    }
+}
+
+/// Where the center of a view with half size `half_view` can go without showing past the edges of
+/// a map of `size`. On an axis where the map is smaller than the view, the map is centered.
+fn clamp_view(center: Vec2, half_view: Vec2, size: Vec2) -> Vec2 {
+    let clamp_axis = |center: f32, half: f32, size: f32| {
+        if size <= half * 2. {
+            size / 2.
+        } else {
+            center.clamp(half, size - half)
+        }
+    };
+    Vec2::new(
+        clamp_axis(center.x, half_view.x, size.x),
+        clamp_axis(center.y, half_view.y, size.y),
+    )
+}
+
+/// Start every night looking at the guard.
+pub fn cut_to_player(mut controller: ResMut<CameraController>) {
+    controller.cut = true;
+}
+
+pub fn camera_input(
+    actions: Res<ActionState>,
+    mut controller: ResMut<CameraController>,
+    mut wheel: EventReader<MouseWheel>,
+    mut camera_query: Query<&mut OrthographicProjection, With<MainCamera>>,
+) {
+    if actions.just_pressed(Action::RoomCamera) {
+        controller.snap_to_room = !controller.snap_to_room;
+    }
+
+    let lines = wheel
+        .iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        })
+        .sum::<f32>();
+    if lines == 0. {
+        return;
+    }
+
+    let mut projection = match camera_query.single_mut() {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Main Camera not found: {}", e);
+            return;
+        }
+    };
+    // scrolling up zooms in
+    projection.scale = (projection.scale * (1. - controller.zoom_step).powf(lines))
+        .clamp(controller.min_zoom, controller.max_zoom);
+}
+
+/// Ease the camera towards the guard, or the room they're in, without showing past the edges of
+/// the map.
+pub fn follow_player(
+    mut controller: ResMut<CameraController>,
+    map: Res<Map>,
+    overlaps: Res<TriggerOverlaps>,
+    windows: Res<Windows>,
+    player_query: Query<(Entity, &GlobalTransform), With<Player>>,
+    room_query: Query<&GlobalTransform, With<TriggerVolume>>,
+    mut camera_query: Query<(&mut Transform, &OrthographicProjection), With<MainCamera>>,
+) {
+    let (player, player_tran) = match player_query.single() {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    let (mut camera, projection) = match camera_query.single_mut() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Main Camera not found: {}", e);
+            return;
+        }
+    };
+
+    let position = camera.translation.truncate();
+    let guard = player_tran.translation.truncate();
+    let room = if controller.snap_to_room {
+        overlaps
+            .inside(player)
+            .find_map(|(trigger, kind)| match kind {
+                TriggerKind::Room(_) => room_query.get(trigger).ok(),
+                _ => None,
+            })
+    } else {
+        None
+    };
+
+    let mut next = match room {
+        Some(room) => room.translation.truncate(),
+        None if controller.cut => guard,
+        // only move as far as it takes to get the guard back inside the dead zone
+        None => {
+            let offset = guard - position;
+            position + (offset.abs() - controller.dead_zone).max(Vec2::ZERO) * offset.signum()
+        }
+    };
+    if !controller.cut {
+        let catch_up = 1. - (-controller.damping * TIMESTEP as f32).exp();
+        next = position + (next - position) * catch_up;
+    }
+    controller.cut = false;
+
+    if let Some(window) = windows.get_primary() {
+        let half_view = Vec2::new(window.width(), window.height()) / 2. * projection.scale;
+        let size = Vec2::new(map.width() as f32, map.height() as f32) * TILE_SIZE;
+        next = clamp_view(next, half_view, size);
+    }
+
+    // the camera keeps its own z, or it would end up level with the sprites
+    camera.translation.x = next.x;
+    camera.translation.y = next.y;
+}
@@ -0,0 +1,131 @@
+use crate::behaviour::{Behaviour, BehaviourState};
+use crate::collision::Aabb;
+use crate::door::LockedDoors;
+use crate::intruder::Intruder;
+use crate::mapgen::{Map, TileKind};
+use crate::movement::{MovementParams, Stamina};
+use crate::room::TILE_SIZE;
+use crate::{Collider, Player, TIMESTEP};
+use bevy::prelude::*;
+use rand::Rng;
+use std::time::Duration;
+
+/// How far apart the guard's and an intruder's colliders can be and still count as touching.
+const REACH: f32 = 4.;
+/// Chance of hanging on to an intruder with no stamina left.
+const CATCH_CHANCE: f32 = 0.5;
+/// Extra chance with full stamina, shrinking as the guard tires.
+const STAMINA_BONUS: f32 = 0.35;
+/// An intruder that's already running is harder to hold.
+const FLEEING_PENALTY: f32 = 0.2;
+/// Stamina the guard loses when an intruder breaks away.
+const STRUGGLE_COST: f32 = 1.5;
+/// How far an intruder shoves off the guard when it breaks away.
+const SHOVE: f32 = TILE_SIZE / 2.;
+const BREAK_AWAY_SECONDS: f32 = 1.5;
+
+pub struct IntruderCaught {
+    pub intruder: Entity,
+    /// Had nowhere to go, so there was no struggle.
+    pub cornered: bool,
+}
+
+pub struct IntruderBrokeAway {
+    pub intruder: Entity,
+}
+
+/// Just got out of the guard's grip and can't be grabbed again until the timer runs out.
+pub struct BrokeAway(Timer);
+
+/// Whether `position` is inside a room with every door out of it locked.
+pub fn cornered(map: &Map, locked: &LockedDoors, position: Vec2) -> bool {
+    let room = match map
+        .world_to_tile(position.x, position.y)
+        .map(|tile| map.occupied[tile])
+    {
+        Some(TileKind::Room(room)) => room,
+        _ => return false,
+    };
+    map.doors
+        .iter()
+        .enumerate()
+        .filter(|(_, door)| door.room == room)
+        .all(|(id, _)| locked.0.contains(&id))
+}
+
+/// Grab any intruder the guard runs into. Cornered intruders give up, anything else might break
+/// away and run, costing the guard stamina.
+pub fn confront_intruders(
+    mut commands: Commands,
+    map: Res<Map>,
+    locked: Res<LockedDoors>,
+    params: Res<MovementParams>,
+    mut player_query: Query<(&GlobalTransform, &Transform, &Collider, &mut Stamina), With<Player>>,
+    mut intruder_query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &mut Transform,
+            &Collider,
+            &Behaviour,
+            Option<&mut BrokeAway>,
+        ),
+        (With<Intruder>, Without<Player>),
+    >,
+    mut caught: EventWriter<IntruderCaught>,
+    mut broke_away: EventWriter<IntruderBrokeAway>,
+) {
+    let (global, local, collider, mut stamina) = match player_query.single_mut() {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+    let mut guard = Aabb::of(global, local, collider);
+    guard.half += Vec2::splat(REACH);
+
+    let mut rng = rand::thread_rng();
+    for (entity, global, mut tran, collider, behaviour, recovering) in intruder_query.iter_mut() {
+        if let Some(mut recovering) = recovering {
+            if !recovering
+                .0
+                .tick(Duration::from_secs_f64(TIMESTEP))
+                .finished()
+            {
+                continue;
+            }
+            commands.entity(entity).remove::<BrokeAway>();
+        }
+
+        let intruder = Aabb::of(global, &tran, collider);
+        if !guard.overlaps(&intruder) {
+            continue;
+        }
+
+        let cornered = cornered(&map, &locked, intruder.center);
+        let mut chance = CATCH_CHANCE + STAMINA_BONUS * stamina.current / params.stamina;
+        if behaviour.state == BehaviourState::Fleeing {
+            chance -= FLEEING_PENALTY;
+        }
+
+        if cornered || rng.gen::<f32>() < chance {
+            commands.entity(entity).despawn_recursive();
+            caught.send(IntruderCaught {
+                intruder: entity,
+                cornered,
+            });
+        } else {
+            stamina.current = (stamina.current - STRUGGLE_COST).max(0.);
+            stamina.winded |= stamina.current == 0.;
+
+            // swept by `collision` like any other move, so it can't go through walls
+            let away = (intruder.center - guard.center).normalize_or_zero() * SHOVE;
+            tran.translation += away.extend(0.);
+            commands
+                .entity(entity)
+                .insert(BrokeAway(Timer::from_seconds(BREAK_AWAY_SECONDS, false)));
+            broke_away.send(IntruderBrokeAway { intruder: entity });
+        }
+    }
+}
@@ -0,0 +1,497 @@
+use crate::door::Door;
+use crate::room::TILE_SIZE;
+use crate::{Collider, Nonstatic};
+use bevy::prelude::*;
+use ncollide2d::na::{Isometry2, Point2, Vector2};
+use ncollide2d::query::{Ray, RayCast};
+use ncollide2d::shape::Cuboid;
+use std::collections::HashMap;
+
+/// How many times a move can slide off a wall before giving up on the rest of it.
+const MAX_SLIDES: usize = 3;
+/// Gap left between a moving collider and whatever it hit, so it doesn't start the next tick
+/// touching it.
+const SKIN: f32 = 0.01;
+
+/// A coordinate frame: what a parent's `GlobalTransform` does to its children's transforms.
+#[derive(Clone, Copy)]
+pub struct Frame {
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+}
+
+impl Frame {
+    pub const IDENTITY: Self = Self {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    pub fn new(global: &GlobalTransform) -> Self {
+        Self {
+            translation: global.translation,
+            rotation: global.rotation,
+            scale: global.scale,
+        }
+    }
+
+    /// The frame an entity's `local` transform lives in, recovered from its global transform.
+    pub fn parent_of(global: &GlobalTransform, local: &Transform) -> Self {
+        let rotation = global.rotation * local.rotation.inverse();
+        let scale = global.scale / local.scale;
+        Self {
+            translation: global.translation - rotation * (scale * local.translation),
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn to_world(&self, point: Vec3) -> Vec3 {
+        self.translation + self.rotation * (self.scale * point)
+    }
+
+    pub fn to_local(&self, point: Vec3) -> Vec3 {
+        self.rotation.inverse() * (point - self.translation) / self.scale
+    }
+
+    /// Rotation around the z axis.
+    fn angle(&self) -> f32 {
+        let x = self.rotation * Vec3::X;
+        x.y.atan2(x.x)
+    }
+}
+
+/// A collider's box in world space.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub center: Vec2,
+    pub half: Vec2,
+}
+
+impl Aabb {
+    /// The box of `collider` on an entity at `translation` in `frame`. Collider offsets are in
+    /// the same space as the entity's translation, so they follow the parent's rotation too.
+    pub fn new(frame: &Frame, translation: Vec3, collider: &Collider) -> Self {
+        let size = collider.rotated(frame.angle()).size * frame.scale.truncate().abs();
+        Self {
+            center: frame.to_world(translation + collider.offset).truncate(),
+            half: size / 2.,
+        }
+    }
+
+    pub fn of(global: &GlobalTransform, local: &Transform, collider: &Collider) -> Self {
+        Self::new(
+            &Frame::parent_of(global, local),
+            local.translation,
+            collider,
+        )
+    }
+
+    pub fn min(&self) -> Vec2 {
+        self.center - self.half
+    }
+
+    pub fn max(&self) -> Vec2 {
+        self.center + self.half
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        let local = point - self.center;
+        local.x.abs() < self.half.x && local.y.abs() < self.half.y
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.penetration(other).is_some()
+    }
+
+    /// How far `other` has to move to stop overlapping this box, along the shallowest axis.
+    fn penetration(&self, other: &Aabb) -> Option<Vec2> {
+        let distance = other.center - self.center;
+        let overlap = self.half + other.half - distance.abs();
+        if overlap.x <= 0. || overlap.y <= 0. {
+            return None;
+        }
+
+        Some(if overlap.x < overlap.y {
+            Vec2::new(overlap.x.copysign(distance.x), 0.)
+        } else {
+            Vec2::new(0., overlap.y.copysign(distance.y))
+        })
+    }
+
+    /// Where along `delta` a box of half size `half` starting at `from` first touches this box,
+    /// as a fraction of `delta`, and the normal of the side it hits.
+    fn sweep(&self, from: Vec2, half: Vec2, delta: Vec2) -> Option<(f32, Vec2)> {
+        // sweeping a box against a box is a ray against the two added together
+        let expanded = self.half + half;
+        let shape = Cuboid::new(Vector2::new(expanded.x, expanded.y));
+        let isometry = Isometry2::translation(self.center.x, self.center.y);
+        let ray = Ray::new(Point2::new(from.x, from.y), Vector2::new(delta.x, delta.y));
+        shape
+            .toi_and_normal_with_ray(&isometry, &ray, 1., true)
+            .map(|hit| (hit.toi, Vec2::new(hit.normal.x, hit.normal.y)))
+    }
+}
+
+/// Where a swept box ended up and what it ran into on the way.
+pub struct Sweep {
+    pub position: Vec2,
+    /// Index of each obstacle that was hit and the normal of the side it was hit on.
+    pub contacts: Vec<(usize, Vec2)>,
+}
+
+/// Move a box of half size `half` from `from` by `delta`, stopping at and sliding along
+/// `obstacles`, then push it out of anything it still overlaps.
+pub fn sweep_and_slide(from: Vec2, half: Vec2, mut delta: Vec2, obstacles: &[Aabb]) -> Sweep {
+    let mut position = from;
+    let mut contacts = Vec::new();
+    let start = Aabb { center: from, half };
+    // anything we already overlap is left to depenetration, otherwise we'd never move again
+    let clear = (0..obstacles.len())
+        .filter(|&i| obstacles[i].penetration(&start).is_none())
+        .collect::<Vec<_>>();
+
+    for _ in 0..MAX_SLIDES {
+        if delta == Vec2::ZERO {
+            break;
+        }
+
+        let hit = clear
+            .iter()
+            .filter_map(|&i| {
+                obstacles[i]
+                    .sweep(position, half, delta)
+                    .map(|(toi, normal)| (i, toi, normal))
+            })
+            .min_by(|(_, a, _), (_, b, _)| a.partial_cmp(b).unwrap());
+
+        match hit {
+            Some((i, toi, normal)) => {
+                contacts.push((i, normal));
+                position += delta * toi + normal * SKIN;
+                // keep whatever of the move runs along the wall
+                let remaining = delta * (1. - toi);
+                delta = remaining - normal * remaining.dot(normal);
+            }
+            None => {
+                position += delta;
+                break;
+            }
+        }
+    }
+
+    let position = depenetrate(position, half, obstacles, &mut contacts);
+    Sweep { position, contacts }
+}
+
+/// Push a box out of every obstacle it overlaps.
+fn depenetrate(
+    mut position: Vec2,
+    half: Vec2,
+    obstacles: &[Aabb],
+    contacts: &mut Vec<(usize, Vec2)>,
+) -> Vec2 {
+    for _ in 0..MAX_SLIDES {
+        let moving = Aabb {
+            center: position,
+            half,
+        };
+        let pushes = obstacles
+            .iter()
+            .enumerate()
+            .filter_map(|(i, obstacle)| obstacle.penetration(&moving).map(|p| (i, p)))
+            .collect::<Vec<_>>();
+        contacts.extend(pushes.iter().map(|&(i, p)| (i, p.normalize())));
+
+        let push = pushes
+            .into_iter()
+            .map(|(_, p)| p)
+            .fold(Vec2::ZERO, |push, p| {
+                // contacts on the same axis overlap, only the biggest one counts
+                Vec2::new(
+                    if p.x.abs() > push.x.abs() {
+                        p.x
+                    } else {
+                        push.x
+                    },
+                    if p.y.abs() > push.y.abs() {
+                        p.y
+                    } else {
+                        push.y
+                    },
+                )
+            });
+        if push == Vec2::ZERO {
+            break;
+        }
+        position += push;
+    }
+    position
+}
+
+/// Static colliders bucketed into `TILE_SIZE` cells, so movers only test the ones near them.
+#[derive(Default)]
+pub struct StaticGrid {
+    boxes: Vec<(Entity, Aabb)>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl StaticGrid {
+    fn cell(point: Vec2) -> (i32, i32) {
+        (
+            (point.x / TILE_SIZE).floor() as i32,
+            (point.y / TILE_SIZE).floor() as i32,
+        )
+    }
+
+    fn cells_between(min: Vec2, max: Vec2) -> impl Iterator<Item = (i32, i32)> {
+        let (min_x, min_y) = Self::cell(min);
+        let (max_x, max_y) = Self::cell(max);
+        (min_y..=max_y).flat_map(move |y| (min_x..=max_x).map(move |x| (x, y)))
+    }
+
+    pub fn clear(&mut self) {
+        self.boxes.clear();
+        self.cells.clear();
+    }
+
+    pub fn insert(&mut self, entity: Entity, aabb: Aabb) {
+        let index = self.boxes.len();
+        self.boxes.push((entity, aabb));
+        for cell in Self::cells_between(aabb.min(), aabb.max()) {
+            self.cells.entry(cell).or_default().push(index);
+        }
+    }
+
+    /// Every collider in a cell that touches the box from `min` to `max`.
+    pub fn query(&self, min: Vec2, max: Vec2) -> impl Iterator<Item = &(Entity, Aabb)> {
+        let mut indices = Self::cells_between(min, max)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        // big colliders sit in several cells
+        indices.sort_unstable();
+        indices.dedup();
+        indices.into_iter().map(move |i| &self.boxes[i])
+    }
+}
+
+/// Rebuild the grid whenever a static collider is added, moved or removed.
+pub fn update_static_grid(
+    mut grid: ResMut<StaticGrid>,
+    changed_query: Query<(), (With<Collider>, Without<Nonstatic>, Changed<GlobalTransform>)>,
+    removed: RemovedComponents<Collider>,
+    static_query: Query<(Entity, &GlobalTransform, &Transform, &Collider), Without<Nonstatic>>,
+) {
+    if changed_query.iter().next().is_none() && removed.iter().next().is_none() {
+        return;
+    }
+
+    grid.clear();
+    for (entity, global, local, collider) in static_query.iter() {
+        grid.insert(entity, Aabb::of(global, local, collider));
+    }
+}
+
+/// Put on a nonstatic collider that's been put somewhere new on purpose, like a teleport, so
+/// `collision` takes the new position as it is instead of sweeping there from the old one. It's
+/// still pushed out of anything it ends up inside, but can skip straight through thin walls, so
+/// anything that should be stopped by walls, like a shove, leaves it off. Only lasts the one tick.
+pub struct Teleported;
+
+/// A nonstatic collider ran into a static one. `normal` points away from what was hit.
+pub struct Collided {
+    pub entity: Entity,
+    pub other: Entity,
+    pub normal: Vec2,
+}
+
+/// Sweep every nonstatic collider from where it was last tick to where it's been moved to,
+/// stopping it at walls, closed doors and furniture.
+pub fn collision(
+    mut commands: Commands,
+    mut last_positions: Local<HashMap<Entity, Vec2>>,
+    mut collided: EventWriter<Collided>,
+    grid: Res<StaticGrid>,
+    mut moving_query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &Collider,
+            Option<&Parent>,
+            Option<&Teleported>,
+        ),
+        With<Nonstatic>,
+    >,
+    door_query: Query<&Door>,
+    parent_query: Query<&GlobalTransform>,
+) {
+    // forget anything that's been despawned or stopped moving
+    last_positions.retain(|&entity, _| moving_query.get_mut(entity).is_ok());
+
+    for (entity, mut tran, collider, parent, teleported) in moving_query.iter_mut() {
+        let frame = parent
+            .and_then(|parent| parent_query.get(parent.0).ok())
+            .map_or(Frame::IDENTITY, Frame::new);
+        let target = Aabb::new(&frame, tran.translation, collider);
+
+        let from = match last_positions.get(&entity) {
+            Some(&from) if teleported.is_none() => from,
+            _ => target.center,
+        };
+        if teleported.is_some() {
+            commands.entity(entity).remove::<Teleported>();
+        }
+
+        // only what's around the whole move can be hit
+        let (others, obstacles): (Vec<_>, Vec<_>) = grid
+            .query(
+                from.min(target.center) - target.half,
+                from.max(target.center) + target.half,
+            )
+            // open doors don't block anything
+            .filter(|(entity, _)| !door_query.get(*entity).map_or(false, Door::is_open))
+            .copied()
+            .unzip();
+        let sweep = sweep_and_slide(from, target.half, target.center - from, &obstacles);
+        for (i, normal) in sweep.contacts {
+            collided.send(Collided {
+                entity,
+                other: others[i],
+                normal,
+            });
+        }
+        let resolved = sweep.position;
+
+        if resolved != target.center {
+            let correction: Vec3 = (resolved - target.center, 0.).into();
+            let world = frame.to_world(tran.translation) + correction;
+            tran.translation = frame.to_local(world);
+        }
+        last_positions.insert(entity, resolved);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HALF: Vec2 = Vec2::ONE;
+
+    fn aabb(x: f32, y: f32, half_x: f32, half_y: f32) -> Aabb {
+        Aabb {
+            center: Vec2::new(x, y),
+            half: Vec2::new(half_x, half_y),
+        }
+    }
+
+    fn assert_near(a: Vec2, b: Vec2) {
+        assert!((a - b).length() < 1e-4, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn moves_freely_in_the_open() {
+        let sweep = sweep_and_slide(
+            Vec2::ZERO,
+            HALF,
+            Vec2::new(5., 3.),
+            &[aabb(0., 20., 1., 1.)],
+        );
+        assert_near(sweep.position, Vec2::new(5., 3.));
+        assert!(sweep.contacts.is_empty());
+    }
+
+    #[test]
+    fn stops_at_a_wall() {
+        let wall = aabb(10., 0., 1., 10.);
+        let sweep = sweep_and_slide(Vec2::ZERO, HALF, Vec2::new(20., 0.), &[wall]);
+        assert_near(sweep.position, Vec2::new(8. - SKIN, 0.));
+        assert_eq!(sweep.contacts, vec![(0, -Vec2::X)]);
+    }
+
+    #[test]
+    fn slides_along_a_wall() {
+        let wall = aabb(10., 0., 1., 10.);
+        let sweep = sweep_and_slide(Vec2::ZERO, HALF, Vec2::new(20., 5.), &[wall]);
+        // the part of the move into the wall is lost, the rest carries on along it
+        assert_near(sweep.position, Vec2::new(8. - SKIN, 5.));
+        assert_eq!(sweep.contacts, vec![(0, -Vec2::X)]);
+    }
+
+    #[test]
+    fn stops_in_a_corner() {
+        let obstacles = [aabb(10., 0., 1., 10.), aabb(0., 10., 10., 1.)];
+        let sweep = sweep_and_slide(Vec2::ZERO, HALF, Vec2::new(20., 10.), &obstacles);
+        assert_near(sweep.position, Vec2::new(8. - SKIN, 8. - SKIN));
+        assert_eq!(sweep.contacts, vec![(0, -Vec2::X), (1, -Vec2::Y)]);
+    }
+
+    #[test]
+    fn slides_past_the_end_of_a_wall() {
+        let wall = aabb(10., 0., 1., 2.);
+        let sweep = sweep_and_slide(Vec2::ZERO, HALF, Vec2::new(20., 1.), &[wall]);
+        assert_near(sweep.position, Vec2::new(8. - SKIN, 1.));
+
+        // clipping the corner still stops it
+        let sweep = sweep_and_slide(Vec2::new(0., 2.5), HALF, Vec2::new(20., 0.), &[wall]);
+        assert_near(sweep.position, Vec2::new(8. - SKIN, 2.5));
+
+        // but just missing it doesn't
+        let sweep = sweep_and_slide(Vec2::new(0., 3.5), HALF, Vec2::new(20., 0.), &[wall]);
+        assert_near(sweep.position, Vec2::new(20., 3.5));
+        assert!(sweep.contacts.is_empty());
+    }
+
+    #[test]
+    fn walks_out_of_what_it_started_in() {
+        // already overlapping the wall, so the move isn't stopped by it, just pushed back out
+        let wall = aabb(1., 0., 1., 10.);
+        let sweep = sweep_and_slide(Vec2::ZERO, HALF, Vec2::new(-3., 0.), &[wall]);
+        assert_near(sweep.position, Vec2::new(-3., 0.));
+        assert!(sweep.contacts.is_empty());
+    }
+
+    #[test]
+    fn pushed_out_along_the_shallowest_axis() {
+        let mut contacts = Vec::new();
+        let position = depenetrate(
+            Vec2::new(8.5, 3.),
+            HALF,
+            &[aabb(10., 0., 1., 10.)],
+            &mut contacts,
+        );
+        assert_near(position, Vec2::new(8., 3.));
+        assert_eq!(contacts, vec![(0, -Vec2::X)]);
+    }
+
+    #[test]
+    fn pushed_out_of_a_corner() {
+        let obstacles = [aabb(10., 0., 1., 10.), aabb(0., 10., 10., 1.)];
+        let mut contacts = Vec::new();
+        let position = depenetrate(Vec2::new(8.5, 8.25), HALF, &obstacles, &mut contacts);
+        assert_near(position, Vec2::new(8., 8.));
+        assert_eq!(contacts, vec![(0, -Vec2::X), (1, -Vec2::Y)]);
+    }
+
+    #[test]
+    fn same_side_pushes_do_not_add_up() {
+        // two boxes side by side along the same wall only push it out once
+        let obstacles = [aabb(10., -1., 1., 1.), aabb(10., 1., 1., 1.)];
+        let mut contacts = Vec::new();
+        let position = depenetrate(Vec2::new(8.5, 0.), HALF, &obstacles, &mut contacts);
+        assert_near(position, Vec2::new(8., 0.));
+    }
+
+    #[test]
+    fn gives_up_when_wedged() {
+        // too narrow to fit, so it bounces between the walls until it runs out of tries
+        let obstacles = [aabb(-1.5, 0., 1., 10.), aabb(1.6, 0., 1., 10.)];
+        let mut contacts = Vec::new();
+        let position = depenetrate(Vec2::ZERO, HALF, &obstacles, &mut contacts);
+        // one pass for each of the `MAX_SLIDES` tries
+        assert_near(position, Vec2::new(0.5, 0.));
+        assert_eq!(contacts.len(), 4);
+    }
+}
@@ -0,0 +1,414 @@
+use crate::furniture::*;
+use crate::input::{Action, ActionState};
+use crate::mapgen::Map;
+use crate::room::*;
+use crate::state::NightEntity;
+use crate::trigger::*;
+use crate::{Collider, Player};
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+/// How close the player has to be to a door to open or close it.
+const INTERACT_RANGE: f32 = 48.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DoorState {
+    Open,
+    Closed,
+    Locked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DoorAction {
+    Open,
+    Close,
+    Toggle,
+    Lock,
+    Unlock,
+    ToggleLock,
+}
+
+impl DoorState {
+    /// The state a door ends up in after `action`. Locked doors have to be unlocked before
+    /// they'll open, and locking an open door slams it shut.
+    pub fn apply(self, action: DoorAction) -> Self {
+        use DoorState::*;
+        match action {
+            DoorAction::Lock => Locked,
+            DoorAction::Unlock if self == Locked => Closed,
+            DoorAction::Unlock => self,
+            DoorAction::ToggleLock if self == Locked => Closed,
+            DoorAction::ToggleLock => Locked,
+            _ if self == Locked => Locked,
+            DoorAction::Open => Open,
+            DoorAction::Close => Closed,
+            DoorAction::Toggle if self == Open => Closed,
+            DoorAction::Toggle => Open,
+        }
+    }
+}
+
+pub struct Door {
+    pub id: usize,
+    pub state: DoorState,
+}
+
+impl Door {
+    pub fn is_open(&self) -> bool {
+        self.state == DoorState::Open
+    }
+}
+
+/// Ask for a door to change state. Everything that opens, closes or locks doors goes through
+/// this so the result is reported as a `DoorChanged`.
+pub struct DoorCommand {
+    pub id: usize,
+    pub action: DoorAction,
+    /// Whoever is working the door, if they're standing at it.
+    pub by: Option<Entity>,
+}
+
+pub struct DoorChanged {
+    pub id: usize,
+    pub entity: Entity,
+    /// Carried over from the `DoorCommand`.
+    pub by: Option<Entity>,
+    pub from: DoorState,
+    pub to: DoorState,
+}
+
+/// Door entities indexed by the door id stored in the map's `TileKind::Door` tiles.
+#[derive(Default)]
+pub struct DoorEntities(pub Vec<Entity>);
+
+/// Ids of every locked door, for anything that paths over the map.
+#[derive(Default)]
+pub struct LockedDoors(pub HashSet<usize>);
+
+pub struct DoorMaterials {
+    open: Handle<ColorMaterial>,
+    closed: Handle<ColorMaterial>,
+    locked: Handle<ColorMaterial>,
+}
+
+impl FromWorld for DoorMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.get_resource::<AssetServer>().unwrap().clone();
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        let closed = asset_server.load("furniture/Door_closed.png");
+        Self {
+            open: materials.add(asset_server.load("furniture/Door_open.png").into()),
+            closed: materials.add(closed.clone().into()),
+            locked: materials.add(ColorMaterial::modulated_texture(
+                closed,
+                Color::rgb(1., 0.5, 0.5),
+            )),
+        }
+    }
+}
+
+/// Spawn a closed door in the wall gap of every door on the map.
+pub fn spawn_doors(commands: &mut Commands, map: &Map, materials: &DoorMaterials) -> DoorEntities {
+    let doors = map
+        .doors
+        .iter()
+        .enumerate()
+        .map(|(id, door)| {
+            // doors sit in the middle of the wall on the facing side of the door tile
+            let position =
+                tile_to_world(door.x, door.y) + door.facing.to_vec2() * (TILE_SIZE / 2. - 8.);
+            let rotation = match door.facing {
+                Direction::North => 0.,
+                Direction::East => -std::f32::consts::FRAC_PI_2,
+                Direction::South => std::f32::consts::PI,
+                Direction::West => std::f32::consts::FRAC_PI_2,
+            };
+
+            let mut transform = Transform::from_xyz(position.x, position.y, 0.5);
+            transform.rotate(Quat::from_rotation_z(rotation));
+            commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(30., 40.)),
+                    material: materials.closed.clone(),
+                    transform,
+                    ..Default::default()
+                })
+                .insert(Door {
+                    id,
+                    state: DoorState::Closed,
+                })
+                .insert(Collider::new(Vec2::new(32., 16.), Vec2::ZERO).rotated(rotation))
+                .insert(TriggerVolume::new(
+                    TriggerKind::Door(id),
+                    TriggerShape::Box(Collider::new(Vec2::splat(INTERACT_RANGE * 2.), Vec2::ZERO)),
+                ))
+                .insert(NightEntity)
+                .id()
+        })
+        .collect();
+
+    DoorEntities(doors)
+}
+
+/// Open/close or lock/unlock the nearest door by hand.
+pub fn interact_doors(
+    actions: Res<ActionState>,
+    console: Res<DoorConsole>,
+    player_query: Query<(Entity, &GlobalTransform), With<Player>>,
+    door_query: Query<(&GlobalTransform, &Door)>,
+    mut door_commands: EventWriter<DoorCommand>,
+) {
+    let action = if actions.just_pressed(Action::Interact) {
+        DoorAction::Toggle
+    } else if actions.just_pressed(Action::ToggleLock) && !console.open {
+        DoorAction::ToggleLock
+    } else {
+        return;
+    };
+
+    let (player_entity, player) = match player_query.single() {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    let nearest = door_query
+        .iter()
+        .map(|(tran, door)| (tran.translation.distance(player.translation), door))
+        .filter(|(distance, _)| *distance < INTERACT_RANGE)
+        .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+
+    if let Some((_, door)) = nearest {
+        door_commands.send(DoorCommand {
+            id: door.id,
+            action,
+            by: Some(player_entity),
+        });
+    }
+}
+
+pub fn apply_door_commands(
+    mut door_commands: EventReader<DoorCommand>,
+    mut door_changes: EventWriter<DoorChanged>,
+    doors: Res<DoorEntities>,
+    mut query: Query<&mut Door>,
+) {
+    for command in door_commands.iter() {
+        let entity = match doors.0.get(command.id) {
+            Some(&e) => e,
+            None => {
+                error!("No door with id {}", command.id);
+                continue;
+            }
+        };
+        let mut door = match query.get_mut(entity) {
+            Ok(d) => d,
+            Err(e) => {
+                error!("Door {} has no entity: {}", command.id, e);
+                continue;
+            }
+        };
+
+        let from = door.state;
+        let to = from.apply(command.action);
+        if from != to {
+            door.state = to;
+            door_changes.send(DoorChanged {
+                id: command.id,
+                entity,
+                by: command.by,
+                from,
+                to,
+            });
+        }
+    }
+}
+
+pub fn track_locked_doors(
+    mut door_changes: EventReader<DoorChanged>,
+    mut locked: ResMut<LockedDoors>,
+) {
+    for change in door_changes.iter() {
+        if change.to == DoorState::Locked {
+            locked.0.insert(change.id);
+        } else {
+            locked.0.remove(&change.id);
+        }
+    }
+}
+
+pub fn update_door_sprites(
+    materials: Res<DoorMaterials>,
+    mut query: Query<(&Door, &mut Handle<ColorMaterial>), Changed<Door>>,
+) {
+    for (door, mut material) in query.iter_mut() {
+        *material = match door.state {
+            DoorState::Open => materials.open.clone(),
+            DoorState::Closed => materials.closed.clone(),
+            DoorState::Locked => materials.locked.clone(),
+        };
+    }
+}
+
+/// Remote door control at the security desk. `DoorConsole` opens the console while standing at
+/// the desk, `SelectUp`/`SelectDown` pick a door and `ToggleLock` locks or unlocks it.
+#[derive(Default)]
+pub struct DoorConsole {
+    pub open: bool,
+    selected: usize,
+}
+
+struct DoorConsoleText;
+
+pub fn setup_door_console(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(5.),
+                    right: Val::Px(5.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+                    font_size: 18.,
+                    color: Color::GREEN,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(DoorConsoleText);
+}
+
+pub fn door_console_input(
+    actions: Res<ActionState>,
+    mut console: ResMut<DoorConsole>,
+    doors: Res<DoorEntities>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    desk_query: Query<(&GlobalTransform, &FurnitureKind)>,
+    mut door_commands: EventWriter<DoorCommand>,
+) {
+    let player = match player_query.single() {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    if !at_security_desk(player.translation, desk_query.iter()) {
+        if console.open {
+            console.open = false;
+        }
+        return;
+    }
+
+    if actions.just_pressed(Action::DoorConsole) {
+        console.open = !console.open;
+    }
+    if !console.open || doors.0.is_empty() {
+        return;
+    }
+
+    let count = doors.0.len();
+    if actions.just_pressed(Action::SelectUp) {
+        console.selected = (console.selected + count - 1) % count;
+    } else if actions.just_pressed(Action::SelectDown) {
+        console.selected = (console.selected + 1) % count;
+    }
+
+    if actions.just_pressed(Action::ToggleLock) {
+        door_commands.send(DoorCommand {
+            id: console.selected,
+            action: DoorAction::ToggleLock,
+            // worked remotely, so the guard can hear it like anyone else
+            by: None,
+        });
+    }
+}
+
+pub fn update_door_console(
+    console: Res<DoorConsole>,
+    map: Res<Map>,
+    door_query: Query<&Door>,
+    mut text_query: Query<&mut Text, With<DoorConsoleText>>,
+) {
+    let mut text = match text_query.single_mut() {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Door console text not found: {}", e);
+            return;
+        }
+    };
+
+    if !console.open {
+        if !text.sections[0].value.is_empty() {
+            text.sections[0].value.clear();
+        }
+        return;
+    }
+
+    let mut doors = door_query.iter().collect::<Vec<_>>();
+    doors.sort_by_key(|d| d.id);
+
+    let mut value = String::from("DOOR CONTROL\n");
+    for door in doors {
+        let cursor = if door.id == console.selected {
+            ">"
+        } else {
+            " "
+        };
+        let location = map
+            .door(door.id)
+            .map(|d| format!("room {} {:?}", d.room, d.facing))
+            .unwrap_or_default();
+        value.push_str(&format!(
+            "{} {:>2} {:<16} {:?}\n",
+            cursor, door.id, location, door.state
+        ));
+    }
+    text.sections[0].value = value;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_action_from_every_state() {
+        use DoorAction as A;
+        use DoorState as S;
+        let table = [
+            (S::Open, A::Open, S::Open),
+            (S::Open, A::Close, S::Closed),
+            (S::Open, A::Toggle, S::Closed),
+            (S::Open, A::Lock, S::Locked),
+            (S::Open, A::Unlock, S::Open),
+            (S::Open, A::ToggleLock, S::Locked),
+            (S::Closed, A::Open, S::Open),
+            (S::Closed, A::Close, S::Closed),
+            (S::Closed, A::Toggle, S::Open),
+            (S::Closed, A::Lock, S::Locked),
+            (S::Closed, A::Unlock, S::Closed),
+            (S::Closed, A::ToggleLock, S::Locked),
+            // locked doors ignore everything but unlocking
+            (S::Locked, A::Open, S::Locked),
+            (S::Locked, A::Close, S::Locked),
+            (S::Locked, A::Toggle, S::Locked),
+            (S::Locked, A::Lock, S::Locked),
+            (S::Locked, A::Unlock, S::Closed),
+            (S::Locked, A::ToggleLock, S::Closed),
+        ];
+        for &(from, action, to) in table.iter() {
+            assert_eq!(from.apply(action), to, "{:?} then {:?}", from, action);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use crate::behaviour::SIGHT_RANGE;
+use crate::intruder::Intruder;
+use crate::mapgen::Map;
+use crate::room::{tile_to_world, TILE_SIZE};
+use crate::security_camera::{Monitor, SecurityCamera, SecurityCameras};
+use crate::state::NightEntity;
+use crate::trap::Revealed;
+use crate::vision::{Occluders, VisionCone};
+use crate::Player;
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+/// Above everything on the map, below the guard's noise cues.
+const FOG_Z: f32 = 2.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FogState {
+    /// Never been seen tonight.
+    Unseen,
+    /// Seen before, but not right now.
+    Remembered,
+    Visible,
+}
+
+/// What the guard knows of each tile, and the fog sprite covering it.
+pub struct Fog {
+    tiles: Vec<(Entity, FogState)>,
+    width: usize,
+}
+
+impl Fog {
+    pub fn state(&self, (x, y): (usize, usize)) -> FogState {
+        self.tiles[y * self.width + x].1
+    }
+
+    pub fn discovered(&self, tile: (usize, usize)) -> bool {
+        self.state(tile) != FogState::Unseen
+    }
+
+    pub fn visible(&self, tile: (usize, usize)) -> bool {
+        self.state(tile) == FogState::Visible
+    }
+}
+
+pub struct FogMaterials {
+    unseen: Handle<ColorMaterial>,
+    remembered: Handle<ColorMaterial>,
+    visible: Handle<ColorMaterial>,
+}
+
+impl FogMaterials {
+    fn get(&self, state: FogState) -> Handle<ColorMaterial> {
+        match state {
+            FogState::Unseen => self.unseen.clone(),
+            FogState::Remembered => self.remembered.clone(),
+            FogState::Visible => self.visible.clone(),
+        }
+    }
+}
+
+impl FromWorld for FogMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        Self {
+            unseen: materials.add(Color::BLACK.into()),
+            remembered: materials.add(Color::rgba(0.0, 0.0, 0.0, 0.6).into()),
+            visible: materials.add(Color::NONE.into()),
+        }
+    }
+}
+
+struct FogTile;
+
+/// Cover every tile of the map, the guard hasn't seen any of it yet.
+pub fn spawn_fog(commands: &mut Commands, map: &Map, materials: &FogMaterials) -> Fog {
+    let mut tiles = Vec::with_capacity(map.width() * map.height());
+    for y in 0..map.height() {
+        for x in 0..map.width() {
+            let position = tile_to_world(x, y);
+            let entity = commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(TILE_SIZE, TILE_SIZE)),
+                    material: materials.get(FogState::Unseen),
+                    transform: Transform::from_xyz(position.x, position.y, FOG_Z),
+                    ..Default::default()
+                })
+                .insert(FogTile)
+                .insert(NightEntity)
+                .id();
+            tiles.push((entity, FogState::Unseen));
+        }
+    }
+
+    Fog {
+        tiles,
+        width: map.width(),
+    }
+}
+
+/// Whether any of the tile centered on `center` can be seen. Walls, doors and furniture block
+/// sight but can be seen themselves, so the tile counts as long as the line gets into it.
+fn sees_tile(cone: &VisionCone, origin: Vec2, center: Vec2, occluders: &Occluders) -> bool {
+    let distance = origin.distance(center);
+    cone.contains(origin, center)
+        && occluders.cast(origin, center - origin, distance) >= distance - TILE_SIZE / 2.
+}
+
+/// Clear the fog around the guard, and down the camera feed while they're at the monitor.
+/// Anything that drops out of sight stays dimmed.
+pub fn update_fog(
+    map: Res<Map>,
+    occluders: Res<Occluders>,
+    monitor: Res<Monitor>,
+    cameras: Res<SecurityCameras>,
+    materials: Res<FogMaterials>,
+    mut fog: ResMut<Fog>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    camera_query: Query<(&GlobalTransform, &SecurityCamera)>,
+    mut tile_query: Query<&mut Handle<ColorMaterial>, With<FogTile>>,
+) {
+    let player = match player_query.single() {
+        Ok(p) => p.translation.truncate(),
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    // the guard has no facing, so they see all around them
+    let mut viewers = vec![(player, VisionCone::all_around(SIGHT_RANGE))];
+    if monitor.active {
+        if let Some(Ok((tran, camera))) = cameras.0.get(monitor.feed).map(|&e| camera_query.get(e))
+        {
+            viewers.push((tran.translation.truncate(), camera.cone()));
+        }
+    }
+
+    let mut visible = HashSet::new();
+    for (origin, cone) in viewers {
+        let (x, y) = match map.world_to_tile(origin.x, origin.y) {
+            Some(tile) => tile,
+            None => continue,
+        };
+        let reach = (cone.range / TILE_SIZE).ceil() as usize + 1;
+        for tx in x.saturating_sub(reach)..(x + reach + 1).min(map.width()) {
+            for ty in y.saturating_sub(reach)..(y + reach + 1).min(map.height()) {
+                if sees_tile(&cone, origin, tile_to_world(tx, ty), &occluders) {
+                    visible.insert((tx, ty));
+                }
+            }
+        }
+    }
+
+    let width = fog.width;
+    for (i, (entity, state)) in fog.tiles.iter_mut().enumerate() {
+        let next = if visible.contains(&(i % width, i / width)) {
+            FogState::Visible
+        } else if *state == FogState::Unseen {
+            FogState::Unseen
+        } else {
+            FogState::Remembered
+        };
+        if next == *state {
+            continue;
+        }
+
+        *state = next;
+        if let Ok(mut material) = tile_query.get_mut(*entity) {
+            *material = materials.get(next);
+        }
+    }
+}
+
+/// Intruders only show up where the guard can see right now, or while a trap has revealed them.
+pub fn hide_intruders(
+    map: Res<Map>,
+    fog: Res<Fog>,
+    mut intruder_query: Query<
+        (
+            &GlobalTransform,
+            &mut Visible,
+            Option<&Revealed>,
+            Option<&Children>,
+        ),
+        With<Intruder>,
+    >,
+    mut child_query: Query<&mut Visible, Without<Intruder>>,
+) {
+    for (tran, mut visibility, revealed, children) in intruder_query.iter_mut() {
+        let seen = revealed.is_some()
+            || map
+                .world_to_tile(tran.translation.x, tran.translation.y)
+                .map_or(false, |tile| fog.visible(tile));
+        if visibility.is_visible == seen {
+            continue;
+        }
+
+        visibility.is_visible = seen;
+        // labels and the like go with the intruder
+        for &child in children.into_iter().flat_map(|c| c.iter()) {
+            if let Ok(mut child_visibility) = child_query.get_mut(child) {
+                child_visibility.is_visible = seen;
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::valuable::Valuable;
+use crate::Collider;
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+/// How close the player has to be to the security desk to use it.
+pub const DESK_RANGE: f32 = 80.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FurnitureKind {
+    SecurityDesk,
+    Desk,
+    Monitor,
+    Documents,
+    ServerRack,
+    Safe,
+}
+
+impl FurnitureKind {
+    /// What it's worth to an intruder, if anything.
+    pub fn value(self) -> Option<u32> {
+        match self {
+            FurnitureKind::Documents => Some(100),
+            FurnitureKind::ServerRack => Some(200),
+            FurnitureKind::Safe => Some(500),
+            _ => None,
+        }
+    }
+}
+
+/// What a piece of furniture looks like: an image, or a plain box until there's art for it.
+pub enum FurnitureArt {
+    Image(&'static str),
+    Plain(Color),
+}
+
+pub struct Furniture {
+    pub asset: Handle<ColorMaterial>,
+    pub size: Vec2,
+    // height above the room floor, so monitors draw on top of desks
+    pub z: f32,
+    pub collider: Option<Collider>,
+}
+
+/// A spot in a room that gets filled with one of the `allowed` pieces of furniture when the room
+/// is spawned. `position` is relative to the center of the room.
+#[derive(Clone)]
+pub struct FurnitureSlot {
+    pub position: Vec2,
+    pub rotation: f32,
+    pub allowed: Vec<FurnitureKind>,
+}
+
+impl FurnitureSlot {
+    pub fn new(x: f32, y: f32, rotation: f32, allowed: Vec<FurnitureKind>) -> Self {
+        Self {
+            position: Vec2::new(x, y),
+            rotation,
+            allowed,
+        }
+    }
+}
+
+/// Fill every slot with a random allowed piece of furniture as children of `parent`, which sits
+/// at `origin`.
+pub fn furnish<R: Rng>(
+    parent: &mut ChildBuilder,
+    origin: &Transform,
+    slots: &[FurnitureSlot],
+    furniture: &HashMap<FurnitureKind, Furniture>,
+    rng: &mut R,
+) {
+    for slot in slots {
+        let kind = match slot.allowed.choose(rng) {
+            Some(kind) => *kind,
+            None => continue,
+        };
+        let item = &furniture[&kind];
+
+        let mut transform = Transform::from_xyz(slot.position.x, slot.position.y, item.z);
+        transform.rotate(Quat::from_rotation_z(slot.rotation));
+        let mut entity_commands = parent.spawn_bundle(SpriteBundle {
+            sprite: Sprite::new(item.size),
+            material: item.asset.clone_weak(),
+            transform,
+            ..Default::default()
+        });
+        entity_commands.insert(kind);
+
+        if let Some(collider) = item.collider {
+            entity_commands.insert(collider.rotated(slot.rotation));
+        }
+        if let Some(value) = kind.value() {
+            entity_commands.insert(Valuable {
+                kind,
+                value,
+                position: origin.mul_vec3(slot.position.extend(0.)).truncate(),
+            });
+        }
+    }
+}
+
+/// Whether `position` is close enough to a security desk to use it.
+pub fn at_security_desk<'a>(
+    position: Vec3,
+    mut furniture: impl Iterator<Item = (&'a GlobalTransform, &'a FurnitureKind)>,
+) -> bool {
+    furniture.any(|(tran, kind)| {
+        *kind == FurnitureKind::SecurityDesk && tran.translation.distance(position) < DESK_RANGE
+    })
+}
+
+pub fn load_furniture(
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut furniture: ResMut<HashMap<FurnitureKind, Furniture>>,
+) {
+    *furniture = new_furniture(|art| match art {
+        FurnitureArt::Image(path) => materials.add(asset_server.load(path).into()),
+        FurnitureArt::Plain(color) => materials.add(color.into()),
+    });
+}
+
+/// Build every kind of furniture, using `material` to turn its art into a material.
+pub fn new_furniture(
+    mut material: impl FnMut(FurnitureArt) -> Handle<ColorMaterial>,
+) -> HashMap<FurnitureKind, Furniture> {
+    use FurnitureArt::*;
+    let mut furniture = HashMap::new();
+    furniture.insert(
+        FurnitureKind::SecurityDesk,
+        Furniture {
+            asset: material(Image("furniture/Security Desk.png")),
+            size: Vec2::new(90., 100.),
+            z: 0.1,
+            collider: Some(Collider::new(Vec2::new(90., 58.), Vec2::new(0., -21.))),
+        },
+    );
+    furniture.insert(
+        FurnitureKind::Desk,
+        Furniture {
+            asset: material(Image("furniture/Security Desk.png")),
+            size: Vec2::new(72., 80.),
+            z: 0.1,
+            collider: Some(Collider::new(Vec2::new(72., 46.), Vec2::new(0., -17.))),
+        },
+    );
+    furniture.insert(
+        FurnitureKind::Monitor,
+        Furniture {
+            asset: material(Image("furniture/Security Monitor.png")),
+            size: Vec2::new(20., 16.),
+            z: 0.2,
+            collider: None,
+        },
+    );
+    // valuables don't block anything, so intruders can walk right up to them
+    furniture.insert(
+        FurnitureKind::Documents,
+        Furniture {
+            asset: material(Plain(Color::ANTIQUE_WHITE)),
+            size: Vec2::new(14., 18.),
+            z: 0.2,
+            collider: None,
+        },
+    );
+    furniture.insert(
+        FurnitureKind::ServerRack,
+        Furniture {
+            asset: material(Plain(Color::MIDNIGHT_BLUE)),
+            size: Vec2::new(40., 20.),
+            z: 0.1,
+            collider: None,
+        },
+    );
+    furniture.insert(
+        FurnitureKind::Safe,
+        Furniture {
+            asset: material(Plain(Color::DARK_GRAY)),
+            size: Vec2::new(28., 28.),
+            z: 0.1,
+            collider: None,
+        },
+    );
+    furniture
+}
+
+pub fn security_room_slots() -> Vec<FurnitureSlot> {
+    use FurnitureKind::*;
+    vec![
+        FurnitureSlot::new(0., 56., 0., vec![SecurityDesk]),
+        FurnitureSlot::new(-20., 35., 0., vec![Monitor]),
+        FurnitureSlot::new(20., 35., 0., vec![Monitor]),
+    ]
+}
+
+pub fn office_slots() -> Vec<FurnitureSlot> {
+    use FurnitureKind::*;
+    vec![
+        FurnitureSlot::new(64., 40., 0., vec![Desk]),
+        FurnitureSlot::new(64., 23., 0., vec![Monitor]),
+        FurnitureSlot::new(64., -40., PI, vec![Desk]),
+        FurnitureSlot::new(64., -23., PI, vec![Monitor]),
+        FurnitureSlot::new(80., 50., 0., vec![Documents]),
+    ]
+}
+
+pub fn server_room_slots() -> Vec<FurnitureSlot> {
+    use FurnitureKind::*;
+    vec![
+        FurnitureSlot::new(-96., 70., 0., vec![ServerRack]),
+        FurnitureSlot::new(32., 70., 0., vec![ServerRack]),
+        FurnitureSlot::new(96., 70., 0., vec![ServerRack]),
+    ]
+}
+
+pub fn vault_slots() -> Vec<FurnitureSlot> {
+    use FurnitureKind::*;
+    vec![
+        FurnitureSlot::new(96., 0., 0., vec![Safe]),
+        FurnitureSlot::new(-96., 64., 0., vec![Documents]),
+    ]
+}
@@ -0,0 +1,671 @@
+use bevy::input::gamepad::{Gamepad, GamepadEvent, GamepadEventType};
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+
+/// Where bindings are loaded from and saved to.
+pub const BINDINGS_PATH: &str = "config/bindings.cfg";
+/// How far a stick has to be pushed before it counts.
+const STICK_DEADZONE: f32 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Run,
+    Sneak,
+    Interact,
+    ToggleLock,
+    DoorConsole,
+    SelectUp,
+    SelectDown,
+    ToggleMonitor,
+    NextFeed,
+    PrevFeed,
+    ToggleTracker,
+    SelectNoiseMaker,
+    SelectTripwire,
+    SelectStickyFloor,
+    PlaceTrap,
+    RoomCamera,
+    Overview,
+    MapDebug,
+    BehaviourDebug,
+    VisionDebug,
+}
+
+impl Action {
+    pub const ALL: [Action; 24] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Run,
+        Action::Sneak,
+        Action::Interact,
+        Action::ToggleLock,
+        Action::DoorConsole,
+        Action::SelectUp,
+        Action::SelectDown,
+        Action::ToggleMonitor,
+        Action::NextFeed,
+        Action::PrevFeed,
+        Action::ToggleTracker,
+        Action::SelectNoiseMaker,
+        Action::SelectTripwire,
+        Action::SelectStickyFloor,
+        Action::PlaceTrap,
+        Action::RoomCamera,
+        Action::Overview,
+        Action::MapDebug,
+        Action::BehaviourDebug,
+        Action::VisionDebug,
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|action| format!("{:?}", action) == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Qwerty,
+    Colemak,
+    Dvorak,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 3] = [Layout::Qwerty, Layout::Colemak, Layout::Dvorak];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|layout| format!("{:?}", layout).eq_ignore_ascii_case(name))
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Layout::Qwerty => Layout::Colemak,
+            Layout::Colemak => Layout::Dvorak,
+            Layout::Dvorak => Layout::Qwerty,
+        }
+    }
+}
+
+/// Keys that can be bound, and the names they go by in the config file.
+const BINDABLE_KEYS: [KeyCode; 67] = {
+    use KeyCode::*;
+    [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Key1, Key2,
+        Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, Up, Down, Left, Right, Space, Tab, Return,
+        LShift, RShift, LControl, RControl, LAlt, RAlt, Comma, Period, Semicolon, Apostrophe,
+        Slash, Backslash, LBracket, RBracket, Minus, Equals, Grave, Insert, Delete, PageUp,
+        PageDown, F1, F2, F3,
+    ]
+};
+
+/// Gamepad buttons that can be bound. Some share names with keys, so they go after the `|` in
+/// the config file.
+const BINDABLE_BUTTONS: [GamepadButtonType; 19] = {
+    use GamepadButtonType::*;
+    [
+        South,
+        East,
+        North,
+        West,
+        C,
+        Z,
+        LeftTrigger,
+        LeftTrigger2,
+        RightTrigger,
+        RightTrigger2,
+        Select,
+        Start,
+        Mode,
+        LeftThumb,
+        RightThumb,
+        DPadUp,
+        DPadDown,
+        DPadLeft,
+        DPadRight,
+    ]
+};
+
+fn key_from_name(name: &str) -> Option<KeyCode> {
+    BINDABLE_KEYS
+        .iter()
+        .copied()
+        .find(|key| format!("{:?}", key) == name)
+}
+
+fn button_from_name(name: &str) -> Option<GamepadButtonType> {
+    BINDABLE_BUTTONS
+        .iter()
+        .copied()
+        .find(|button| format!("{:?}", button) == name)
+}
+
+/// Parse a space separated list of names, warning about any that aren't recognised.
+fn parse_names<T>(names: &str, action: Action, from_name: impl Fn(&str) -> Option<T>) -> Vec<T> {
+    names
+        .split_whitespace()
+        .filter_map(|name| {
+            let parsed = from_name(name);
+            if parsed.is_none() {
+                warn!("Unknown key or button {} for {:?}", name, action);
+            }
+            parsed
+        })
+        .collect()
+}
+
+/// Names of `bound` joined by `separator`, as written in the config and shown when rebinding.
+fn join_names<T: std::fmt::Debug>(bound: Option<&Vec<T>>, separator: &str) -> String {
+    bound
+        .map(|bound| {
+            bound
+                .iter()
+                .map(|b| format!("{:?}", b))
+                .collect::<Vec<_>>()
+                .join(separator)
+        })
+        .unwrap_or_default()
+}
+
+/// Which keys and gamepad buttons trigger each action.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bindings {
+    pub layout: Layout,
+    pub keys: HashMap<Action, Vec<KeyCode>>,
+    pub buttons: HashMap<Action, Vec<GamepadButtonType>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self::preset(Layout::Qwerty)
+    }
+}
+
+impl Bindings {
+    /// Movement sits under the left hand's home position on every layout. Everything else keeps
+    /// its mnemonic letter unless movement took it. No key or button does two things.
+    pub fn preset(layout: Layout) -> Self {
+        use KeyCode::*;
+        let (up, down, left, right) = match layout {
+            Layout::Qwerty => (W, S, A, D),
+            Layout::Colemak => (W, R, A, S),
+            Layout::Dvorak => (Comma, O, A, E),
+        };
+        let (interact, prev_feed, next_feed) = match layout {
+            Layout::Dvorak => (U, LBracket, RBracket),
+            _ => (E, Comma, Period),
+        };
+
+        let keys = vec![
+            (Action::MoveUp, vec![up]),
+            (Action::MoveDown, vec![down]),
+            (Action::MoveLeft, vec![left]),
+            (Action::MoveRight, vec![right]),
+            (Action::Run, vec![LShift]),
+            (Action::Sneak, vec![LControl]),
+            (Action::Interact, vec![interact]),
+            (Action::ToggleLock, vec![L]),
+            (Action::DoorConsole, vec![Tab]),
+            (Action::SelectUp, vec![Up]),
+            (Action::SelectDown, vec![Down]),
+            (Action::ToggleMonitor, vec![M]),
+            (Action::NextFeed, vec![next_feed]),
+            (Action::PrevFeed, vec![prev_feed]),
+            (Action::ToggleTracker, vec![T]),
+            (Action::SelectNoiseMaker, vec![Key1]),
+            (Action::SelectTripwire, vec![Key2]),
+            (Action::SelectStickyFloor, vec![Key3]),
+            (Action::PlaceTrap, vec![P]),
+            (Action::RoomCamera, vec![C]),
+            (Action::Overview, vec![B]),
+            (Action::MapDebug, vec![F1]),
+            (Action::BehaviourDebug, vec![F2]),
+            (Action::VisionDebug, vec![F3]),
+        ]
+        .into_iter()
+        .collect();
+
+        // the left stick moves the guard, which leaves the d-pad free for picking things in menus
+        let buttons = vec![
+            (Action::Run, vec![GamepadButtonType::LeftThumb]),
+            (Action::Sneak, vec![GamepadButtonType::LeftTrigger2]),
+            (Action::Interact, vec![GamepadButtonType::South]),
+            (Action::ToggleLock, vec![GamepadButtonType::East]),
+            (Action::DoorConsole, vec![GamepadButtonType::Select]),
+            (Action::SelectUp, vec![GamepadButtonType::DPadUp]),
+            (Action::SelectDown, vec![GamepadButtonType::DPadDown]),
+            (Action::ToggleMonitor, vec![GamepadButtonType::North]),
+            (Action::NextFeed, vec![GamepadButtonType::RightTrigger]),
+            (Action::PrevFeed, vec![GamepadButtonType::LeftTrigger]),
+            (Action::ToggleTracker, vec![GamepadButtonType::West]),
+            (Action::PlaceTrap, vec![GamepadButtonType::RightTrigger2]),
+            (Action::RoomCamera, vec![GamepadButtonType::RightThumb]),
+            (Action::Overview, vec![GamepadButtonType::Start]),
+        ]
+        .into_iter()
+        .collect();
+
+        Self {
+            layout,
+            keys,
+            buttons,
+        }
+    }
+
+    /// Read bindings from the config file, falling back to the QWERTY preset for anything
+    /// missing or unreadable.
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(config) => Self::parse(&config),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Parse a config of `layout = <name>` followed by `<Action> = <Key> <Key>... | <Button>...`
+    /// overrides. Without a `|` the action keeps its gamepad buttons. Blank lines and lines
+    /// starting with `#` are skipped.
+    pub fn parse(config: &str) -> Self {
+        let mut bindings = Self::default();
+        for line in config.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => {
+                    warn!("Ignoring binding line without '=': {}", line);
+                    continue;
+                }
+            };
+
+            if name == "layout" {
+                match Layout::from_name(value) {
+                    Some(layout) => bindings = Self::preset(layout),
+                    None => warn!("Unknown layout {}", value),
+                }
+                continue;
+            }
+
+            let action = match Action::from_name(name) {
+                Some(action) => action,
+                None => {
+                    warn!("Unknown action {}", name);
+                    continue;
+                }
+            };
+            let (keys, buttons) = match value.split_once('|') {
+                Some((keys, buttons)) => (keys, Some(buttons)),
+                None => (value, None),
+            };
+            // nothing bound is the same as not being in the map, like in the presets
+            let keys = parse_names(keys, action, key_from_name);
+            if keys.is_empty() {
+                bindings.keys.remove(&action);
+            } else {
+                bindings.keys.insert(action, keys);
+            }
+            if let Some(buttons) = buttons {
+                let buttons = parse_names(buttons, action, button_from_name);
+                if buttons.is_empty() {
+                    bindings.buttons.remove(&action);
+                } else {
+                    bindings.buttons.insert(action, buttons);
+                }
+            }
+        }
+        bindings
+    }
+
+    /// The config file contents for these bindings. Every action is written out so the file
+    /// round trips even if the presets change.
+    pub fn to_config(&self) -> String {
+        let mut config = format!("layout = {:?}\n", self.layout);
+        for action in Action::ALL.iter() {
+            let keys = join_names(self.keys.get(action), " ");
+            let buttons = join_names(self.buttons.get(action), " ");
+            writeln!(config, "{:?} = {} | {}", action, keys, buttons).unwrap();
+        }
+        config
+    }
+
+    pub fn save(&self, path: &str) {
+        let result = std::path::Path::new(path)
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(path, self.to_config()));
+        if let Err(e) = result {
+            error!("Couldn't save bindings to {}: {}", path, e);
+        }
+    }
+}
+
+/// This frame's actions, from every bound key and connected gamepad.
+#[derive(Default)]
+pub struct ActionState {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    /// Movement from keys or the left stick, each axis between -1 and 1.
+    pub movement: Vec2,
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+/// Connected gamepads, kept up to date from connection events.
+#[derive(Default)]
+pub struct ConnectedGamepads(pub Vec<Gamepad>);
+
+pub fn track_gamepads(
+    mut events: EventReader<GamepadEvent>,
+    mut gamepads: ResMut<ConnectedGamepads>,
+) {
+    for GamepadEvent(gamepad, event) in events.iter() {
+        match event {
+            GamepadEventType::Connected => gamepads.0.push(*gamepad),
+            GamepadEventType::Disconnected => gamepads.0.retain(|g| g != gamepad),
+            _ => {}
+        }
+    }
+}
+
+pub fn update_actions(
+    bindings: Res<Bindings>,
+    rebinding: Res<RebindScreen>,
+    gamepads: Res<ConnectedGamepads>,
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut actions: ResMut<ActionState>,
+) {
+    actions.pressed.clear();
+    actions.just_pressed.clear();
+    actions.movement = Vec2::ZERO;
+
+    // the rebinding screen has the keyboard to itself
+    if rebinding.open {
+        return;
+    }
+
+    for &action in Action::ALL.iter() {
+        let bound_keys = bindings.keys.get(&action).map_or(&[][..], |k| &k[..]);
+        let bound_buttons = bindings.buttons.get(&action).map_or(&[][..], |b| &b[..]);
+        let gamepad_buttons = || {
+            gamepads.0.iter().flat_map(move |&gamepad| {
+                bound_buttons
+                    .iter()
+                    .map(move |&button| GamepadButton(gamepad, button))
+            })
+        };
+
+        if bound_keys.iter().any(|&k| keys.pressed(k))
+            || gamepad_buttons().any(|b| buttons.pressed(b))
+        {
+            actions.pressed.insert(action);
+        }
+        if bound_keys.iter().any(|&k| keys.just_pressed(k))
+            || gamepad_buttons().any(|b| buttons.just_pressed(b))
+        {
+            actions.just_pressed.insert(action);
+        }
+    }
+
+    let axis = |positive, negative| {
+        (actions.pressed(positive) as i32 - actions.pressed(negative) as i32) as f32
+    };
+    let mut movement = Vec2::new(
+        axis(Action::MoveRight, Action::MoveLeft),
+        axis(Action::MoveUp, Action::MoveDown),
+    );
+    for &gamepad in gamepads.0.iter() {
+        let stick = Vec2::new(
+            axes.get(GamepadAxis(gamepad, GamepadAxisType::LeftStickX))
+                .unwrap_or(0.),
+            axes.get(GamepadAxis(gamepad, GamepadAxisType::LeftStickY))
+                .unwrap_or(0.),
+        );
+        if stick.length() > STICK_DEADZONE {
+            movement += stick;
+        }
+    }
+    actions.movement = movement.clamp(Vec2::splat(-1.), Vec2::ONE);
+}
+
+/// In-game rebinding. F5 opens it, Up/Down pick an action, Enter waits for a new key or gamepad
+/// button, Backspace cancels and F6 switches layout preset. Closing it saves the bindings.
+#[derive(Default)]
+pub struct RebindScreen {
+    pub open: bool,
+    selected: usize,
+    listening: bool,
+}
+
+struct RebindText;
+
+pub fn setup_rebind_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(60.),
+                    left: Val::Px(60.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+                    font_size: 18.,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(RebindText);
+}
+
+pub fn rebind_input(
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    mut screen: ResMut<RebindScreen>,
+    mut bindings: ResMut<Bindings>,
+) {
+    if keys.just_pressed(KeyCode::F5) {
+        screen.open = !screen.open;
+        screen.listening = false;
+        if !screen.open {
+            bindings.save(BINDINGS_PATH);
+        }
+        return;
+    }
+    if !screen.open {
+        return;
+    }
+
+    let action = Action::ALL[screen.selected];
+    if screen.listening {
+        if keys.just_pressed(KeyCode::Back) {
+            screen.listening = false;
+        } else if let Some(key) = keys
+            .get_just_pressed()
+            .copied()
+            .find(|key| BINDABLE_KEYS.contains(key))
+        {
+            bindings.keys.insert(action, vec![key]);
+            screen.listening = false;
+        } else if let Some(GamepadButton(_, button)) = buttons
+            .get_just_pressed()
+            .copied()
+            .find(|GamepadButton(_, button)| BINDABLE_BUTTONS.contains(button))
+        {
+            bindings.buttons.insert(action, vec![button]);
+            screen.listening = false;
+        }
+        return;
+    }
+
+    let count = Action::ALL.len();
+    if keys.just_pressed(KeyCode::Up) {
+        screen.selected = (screen.selected + count - 1) % count;
+    } else if keys.just_pressed(KeyCode::Down) {
+        screen.selected = (screen.selected + 1) % count;
+    } else if keys.just_pressed(KeyCode::Return) {
+        screen.listening = true;
+    } else if keys.just_pressed(KeyCode::F6) {
+        *bindings = Bindings::preset(bindings.layout.next());
+    }
+}
+
+pub fn update_rebind_text(
+    screen: Res<RebindScreen>,
+    bindings: Res<Bindings>,
+    mut text_query: Query<&mut Text, With<RebindText>>,
+) {
+    if !screen.is_changed() && !bindings.is_changed() {
+        return;
+    }
+
+    let mut text = match text_query.single_mut() {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Rebind text not found: {}", e);
+            return;
+        }
+    };
+
+    if !screen.open {
+        text.sections[0].value.clear();
+        return;
+    }
+
+    let mut value = format!("CONTROLS ({:?}, F6 to change)\n", bindings.layout);
+    for (i, action) in Action::ALL.iter().enumerate() {
+        let cursor = if i == screen.selected { ">" } else { " " };
+        let keys = if i == screen.selected && screen.listening {
+            String::from("press a key or button...")
+        } else {
+            format!(
+                "{} / {}",
+                join_names(bindings.keys.get(action), ", "),
+                join_names(bindings.buttons.get(action), ", ")
+            )
+        };
+        writeln!(value, "{} {:<18} {}", cursor, format!("{:?}", action), keys).unwrap();
+    }
+    text.sections[0].value = value;
+}
+
+/// Everything to do with reading player input.
+pub struct InputActionsPlugin;
+
+impl Plugin for InputActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Bindings::load(BINDINGS_PATH))
+            .init_resource::<ActionState>()
+            .init_resource::<ConnectedGamepads>()
+            .init_resource::<RebindScreen>()
+            .add_startup_system(setup_rebind_screen)
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                track_gamepads.label("track_gamepads").after(InputSystem),
+            )
+            .add_system_to_stage(CoreStage::PreUpdate, update_actions.after("track_gamepads"))
+            .add_system(rebind_input.label("rebind_input"))
+            .add_system(update_rebind_text.after("rebind_input"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_round_trip() {
+        for &layout in Layout::ALL.iter() {
+            let bindings = Bindings::preset(layout);
+            assert_eq!(Bindings::parse(&bindings.to_config()), bindings);
+        }
+    }
+
+    #[test]
+    fn rebinds_round_trip() {
+        let mut bindings = Bindings::preset(Layout::Colemak);
+        bindings
+            .keys
+            .insert(Action::Run, vec![KeyCode::Space, KeyCode::RShift]);
+        bindings.keys.remove(&Action::PlaceTrap);
+        bindings
+            .buttons
+            .insert(Action::MoveUp, vec![GamepadButtonType::DPadUp]);
+        bindings.buttons.remove(&Action::Interact);
+        assert_eq!(Bindings::parse(&bindings.to_config()), bindings);
+    }
+
+    #[test]
+    fn no_input_does_two_things() {
+        for &layout in Layout::ALL.iter() {
+            let bindings = Bindings::preset(layout);
+            let keys = bindings.keys.values().flatten().collect::<Vec<_>>();
+            let unique_keys = keys.iter().collect::<HashSet<_>>();
+            assert_eq!(keys.len(), unique_keys.len(), "{:?}", layout);
+            let buttons = bindings.buttons.values().flatten().collect::<Vec<_>>();
+            let unique_buttons = buttons.iter().collect::<HashSet<_>>();
+            assert_eq!(buttons.len(), unique_buttons.len(), "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn unknown_names_are_skipped() {
+        let bindings = Bindings::parse(
+            "layout = dvorak\n\
+             # a comment\n\
+             Run = LShift Banana | LeftThumb Frisbee\n\
+             Teleport = Space\n\
+             no equals sign here\n",
+        );
+        let mut expected = Bindings::preset(Layout::Dvorak);
+        expected.keys.insert(Action::Run, vec![KeyCode::LShift]);
+        expected
+            .buttons
+            .insert(Action::Run, vec![GamepadButtonType::LeftThumb]);
+        assert_eq!(bindings, expected);
+
+        // an unknown layout leaves the default preset alone
+        assert_eq!(Bindings::parse("layout = azerty"), Bindings::default());
+    }
+
+    #[test]
+    fn buttons_are_kept_without_a_bar() {
+        let bindings = Bindings::parse("Interact = F\nPlaceTrap = P |");
+        assert_eq!(bindings.keys[&Action::Interact], vec![KeyCode::F]);
+        assert_eq!(
+            bindings.buttons[&Action::Interact],
+            vec![GamepadButtonType::South]
+        );
+        // an empty list after the bar unbinds the buttons
+        assert!(!bindings.buttons.contains_key(&Action::PlaceTrap));
+    }
+}
@@ -0,0 +1,336 @@
+use crate::behaviour::*;
+use crate::door::*;
+use crate::mapgen::{Map, TileKind};
+use crate::noise::Listener;
+use crate::room::*;
+use crate::state::NightEntity;
+use crate::trap::{Slowed, SLOW_FACTOR};
+use crate::valuable::Valuables;
+use crate::{Collider, Nonstatic};
+use bevy::prelude::*;
+use rand::Rng;
+use std::collections::VecDeque;
+
+pub struct Intruder {
+    /// Distance moved per tick.
+    pub speed: f32,
+}
+
+/// Where an intruder is headed and the tiles it still has to walk through to get there.
+#[derive(Default)]
+pub struct IntruderPath {
+    pub target: Option<(usize, usize)>,
+    pub waypoints: VecDeque<(usize, usize)>,
+}
+
+impl IntruderPath {
+    /// Whether a door somewhere ahead on the path has been locked.
+    pub fn blocked(&self, map: &Map, locked: &LockedDoors) -> bool {
+        self.waypoints
+            .iter()
+            .any(|&(x, y)| match map.occupied[(x, y)] {
+                TileKind::Door { id, .. } => locked.0.contains(&id),
+                _ => false,
+            })
+    }
+}
+
+/// Spawn an intruder at the building entrance.
+pub fn spawn_intruder(
+    commands: &mut Commands,
+    map: &Map,
+    material: Handle<ColorMaterial>,
+    speed: f32,
+) -> Option<Entity> {
+    let (x, y) = map.entrance()?;
+    let position = tile_to_world(x, y);
+
+    let entity = commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(14., 14.)),
+            material,
+            transform: Transform::from_xyz(position.x, position.y, 1.),
+            ..Default::default()
+        })
+        .insert(Intruder { speed })
+        .insert(IntruderPath::default())
+        .insert(Behaviour::default())
+        .insert(Senses::default())
+        .insert(Listener)
+        .insert(Collider {
+            size: Vec2::new(14., 14.),
+            ..Default::default()
+        })
+        .insert(Nonstatic)
+        .insert(NightEntity)
+        .id();
+    Some(entity)
+}
+
+/// The tile of a valuable still in the building.
+pub fn pick_target<R: Rng>(
+    map: &Map,
+    valuables: &Valuables,
+    rng: &mut R,
+) -> Option<(usize, usize)> {
+    valuables.pick(rng).and_then(|valuable| valuable.tile(map))
+}
+
+/// Tiles to walk through to get from `position` to `target` without going through locked doors.
+pub fn plan_path(
+    map: &Map,
+    position: Vec2,
+    target: (usize, usize),
+    locked: &LockedDoors,
+) -> Option<VecDeque<(usize, usize)>> {
+    let start = map.world_to_tile(position.x, position.y)?;
+    map.find_path(start, target, |id| locked.0.contains(&id))
+        .map(|path| path.into_iter().collect())
+}
+
+/// Move `position` up to `speed` along the waypoints, dropping the ones that get reached.
+pub fn steer(mut position: Vec2, waypoints: &mut VecDeque<(usize, usize)>, speed: f32) -> Vec2 {
+    let mut remaining = speed;
+    while let Some(&(x, y)) = waypoints.front() {
+        let to_waypoint = tile_to_world(x, y) - position;
+        let distance = to_waypoint.length();
+        if distance > remaining {
+            return position + to_waypoint / distance * remaining;
+        }
+        position += to_waypoint;
+        remaining -= distance;
+        waypoints.pop_front();
+    }
+    position
+}
+
+/// Plan a route to the target room for sneaking intruders. Other states plan their own movement
+/// in `act_on_behaviour`.
+pub fn plan_intruder_paths(
+    map: Res<Map>,
+    locked: Res<LockedDoors>,
+    valuables: Res<Valuables>,
+    mut query: Query<(&GlobalTransform, &Behaviour, &mut IntruderPath), With<Intruder>>,
+) {
+    let mut rng = rand::thread_rng();
+    for (tran, behaviour, mut path) in query.iter_mut() {
+        if behaviour.state != BehaviourState::Sneaking || !path.waypoints.is_empty() {
+            continue;
+        }
+
+        // already there, the behaviour takes it from here
+        let here = map.world_to_tile(tran.translation.x, tran.translation.y);
+        if path.target.is_some() && path.target == here {
+            continue;
+        }
+
+        // keep going for the same thing after hiding or rerouting, otherwise pick a new one
+        let target = match path.target {
+            Some(target) => target,
+            None => match pick_target(&map, &valuables, &mut rng) {
+                Some(target) => target,
+                None => continue,
+            },
+        };
+
+        match plan_path(&map, tran.translation.truncate(), target, &locked) {
+            Some(waypoints) => {
+                path.target = Some(target);
+                path.waypoints = waypoints;
+            }
+            None => {
+                path.target = None;
+                path.waypoints.clear();
+            }
+        }
+    }
+}
+
+pub fn move_intruders(
+    mut query: Query<(
+        &mut Transform,
+        &Intruder,
+        &Behaviour,
+        Option<&Slowed>,
+        &mut IntruderPath,
+    )>,
+) {
+    for (mut tran, intruder, behaviour, slowed, mut path) in query.iter_mut() {
+        let slow = if slowed.is_some() { SLOW_FACTOR } else { 1. };
+        let position = steer(
+            tran.translation.truncate(),
+            &mut path.waypoints,
+            intruder.speed * behaviour.speed_factor() * slow,
+        );
+        tran.translation.x = position.x;
+        tran.translation.y = position.y;
+    }
+}
+
+/// Open closed doors just ahead of intruders. Locked doors are routed around instead.
+pub fn intruders_open_doors(
+    map: Res<Map>,
+    doors: Res<DoorEntities>,
+    door_query: Query<&Door>,
+    intruder_query: Query<(Entity, &IntruderPath), With<Intruder>>,
+    mut door_commands: EventWriter<DoorCommand>,
+) {
+    for (entity, path) in intruder_query.iter() {
+        for &(x, y) in path.waypoints.iter().take(2) {
+            if let TileKind::Door { id, .. } = map.occupied[(x, y)] {
+                let closed = doors
+                    .0
+                    .get(id)
+                    .and_then(|&e| door_query.get(e).ok())
+                    .map_or(false, |door| door.state == DoorState::Closed);
+                if closed {
+                    door_commands.send(DoorCommand {
+                        id,
+                        action: DoorAction::Open,
+                        by: Some(entity),
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::furniture::FurnitureKind;
+    use crate::mapgen::MapDoor;
+    use crate::valuable::Valuable;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// A room with a door on either side, and a hallway wrapping round it from one door to the
+    /// other:
+    ///
+    /// ```text
+    /// H H H H H
+    /// H R R R H
+    /// H D R D H
+    /// H R R R H
+    /// ```
+    fn ring_map() -> Map {
+        let mut map = Map::new(5, 5);
+        for y in 1..5 {
+            map.occupied[(0, y)] = TileKind::Hallway;
+            map.occupied[(4, y)] = TileKind::Hallway;
+        }
+        for x in 1..4 {
+            map.occupied[(x, 4)] = TileKind::Hallway;
+            for y in 1..4 {
+                map.occupied[(x, y)] = TileKind::Room(0);
+            }
+        }
+        for &(x, facing) in &[(1, Direction::West), (3, Direction::East)] {
+            let id = map.doors.len();
+            map.doors.push(MapDoor {
+                x,
+                y: 2,
+                room: 0,
+                facing,
+            });
+            map.occupied[(x, 2)] = TileKind::Door {
+                room: 0,
+                facing,
+                id,
+            };
+        }
+        map
+    }
+
+    const WEST_DOOR: usize = 0;
+    const EAST_DOOR: usize = 1;
+
+    #[test]
+    fn path_avoids_locked_doors() {
+        let map = ring_map();
+        let path = map.find_path((2, 2), (4, 4), |_| false).unwrap();
+        assert_eq!(path, vec![(2, 2), (3, 2), (4, 2), (4, 3), (4, 4)]);
+
+        // the long way round, out of the other door
+        let path = map.find_path((2, 2), (4, 4), |id| id == EAST_DOOR).unwrap();
+        assert!(!path.contains(&(3, 2)));
+        assert_eq!(&path[..3], &[(2, 2), (1, 2), (0, 2)]);
+        assert_eq!(path.last(), Some(&(4, 4)));
+        assert_eq!(path.len(), 9);
+    }
+
+    #[test]
+    fn no_path_when_locked_in() {
+        let map = ring_map();
+        let locked = |id| id == WEST_DOOR || id == EAST_DOOR;
+        assert_eq!(map.find_path((2, 2), (4, 4), locked), None);
+        assert_eq!(map.find_path((4, 4), (2, 2), locked), None);
+        // still free to walk around inside
+        assert_eq!(
+            map.find_path((2, 1), (2, 3), locked),
+            Some(vec![(2, 1), (2, 2), (2, 3)])
+        );
+    }
+
+    #[test]
+    fn steer_stops_on_the_waypoint() {
+        let start = tile_to_world(0, 0);
+        let mut waypoints = VecDeque::from(vec![(1, 0)]);
+
+        // not there yet, keeps the waypoint
+        let position = steer(start, &mut waypoints, 10.);
+        assert_eq!(position, start + Vec2::new(10., 0.));
+        assert_eq!(waypoints.len(), 1);
+
+        // more than enough speed lands exactly on it rather than overshooting
+        let position = steer(position, &mut waypoints, TILE_SIZE * 3.);
+        assert_eq!(position, tile_to_world(1, 0));
+        assert!(waypoints.is_empty());
+
+        // with nowhere to go it stays put
+        assert_eq!(steer(position, &mut waypoints, 10.), position);
+    }
+
+    #[test]
+    fn steer_carries_on_through_waypoints() {
+        let mut waypoints = VecDeque::from(vec![(1, 0), (1, 1)]);
+        let position = steer(tile_to_world(0, 0), &mut waypoints, TILE_SIZE * 1.5);
+        assert_eq!(
+            position,
+            tile_to_world(1, 0) + Vec2::new(0., TILE_SIZE / 2.)
+        );
+        assert_eq!(waypoints, VecDeque::from(vec![(1, 1)]));
+    }
+
+    #[test]
+    fn targets_are_valuables_left_in_the_building() {
+        let map = ring_map();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut world = World::new();
+        let mut valuables = Valuables::default();
+        assert_eq!(pick_target(&map, &valuables, &mut rng), None);
+
+        let tiles = [(2, 2), (4, 4)];
+        for &(x, y) in tiles.iter() {
+            valuables.remaining.push((
+                world.spawn().id(),
+                Valuable {
+                    kind: FurnitureKind::Safe,
+                    value: 500,
+                    position: tile_to_world(x, y),
+                },
+            ));
+        }
+        for _ in 0..20 {
+            let target = pick_target(&map, &valuables, &mut rng).unwrap();
+            assert!(tiles.contains(&target));
+        }
+
+        // anything that's been stolen is off the list
+        let (_, stolen) = valuables.remaining.remove(0);
+        valuables.stolen.push(stolen);
+        for _ in 0..20 {
+            assert_eq!(pick_target(&map, &valuables, &mut rng), Some(tiles[1]));
+        }
+    }
+}
@@ -0,0 +1,61 @@
+pub mod behaviour;
+pub mod camera;
+pub mod catch;
+pub mod collision;
+pub mod door;
+pub mod fog;
+pub mod furniture;
+pub mod input;
+pub mod intruder;
+pub mod mapgen;
+pub mod minimap;
+pub mod movement;
+pub mod night;
+pub mod noise;
+pub mod room;
+pub mod security_camera;
+pub mod state;
+pub mod tracker;
+pub mod trap;
+pub mod trigger;
+pub mod valuable;
+pub mod vision;
+
+use bevy::prelude::*;
+
+/// Length of a gameplay tick, in seconds.
+pub const TIMESTEP: f64 = 1. / 60.;
+
+pub struct Player;
+pub struct MainCamera;
+
+pub struct Nonstatic;
+
+#[derive(Default, Copy, Clone)]
+pub struct Collider {
+    pub size: Vec2,
+    pub offset: Vec3,
+}
+
+impl Collider {
+    pub fn new(size: Vec2, offset: Vec2) -> Self {
+        Self {
+            size,
+            offset: (offset, 0.).into(),
+        }
+    }
+
+    /// The collider of something rotated by `angle` around its origin. Colliders are axis
+    /// aligned, so the angle is snapped to the nearest quarter turn.
+    pub fn rotated(&self, angle: f32) -> Self {
+        let quarter_turns = (angle / std::f32::consts::FRAC_PI_2).round() as i32;
+        let offset = Quat::from_rotation_z(quarter_turns as f32 * std::f32::consts::FRAC_PI_2)
+            .mul_vec3(self.offset);
+        let size = if quarter_turns % 2 == 0 {
+            self.size
+        } else {
+            Vec2::new(self.size.y, self.size.x)
+        };
+        Self { size, offset }
+    }
+}
@@ -1,203 +1,292 @@
-mod mapgen;
-mod room;
+mod mapgen_debug;
 
-use bevy::{core::FixedTimestep, prelude::*, sprite};
-use mapgen::*;
-use room::*;
+use bevy::{core::FixedTimestep, prelude::*};
+use mapgen_debug::*;
+use rand::{rngs::StdRng, SeedableRng};
+use rusty_jam::{
+    behaviour::*, camera::*, catch::*, collision::*, door::*, fog::*, furniture::*, input::*,
+    intruder::*, mapgen::*, minimap::*, movement::*, night::*, noise::*, room::*,
+    security_camera::*, state::*, tracker::*, trap::*, trigger::*, valuable::*, vision::*,
+    Collider, MainCamera, Nonstatic, Player, TIMESTEP,
+};
 use std::collections::HashMap;
 
 fn main() {
     App::new()
         .add_plugins(DefaultPlugins)
+        .add_plugin(InputActionsPlugin)
+        .add_plugin(GameStatePlugin)
         .insert_resource(ClearColor(Color::BLUE))
         .insert_resource(HashMap::<RoomKind, Room>::new())
+        .insert_resource(HashMap::<FurnitureKind, Furniture>::new())
+        .init_resource::<MapDebug>()
+        .init_resource::<MapDebugMaterials>()
+        .init_resource::<DoorMaterials>()
+        .init_resource::<DoorConsole>()
+        .init_resource::<LockedDoors>()
+        .init_resource::<Monitor>()
+        .init_resource::<BehaviourTable>()
+        .init_resource::<BehaviourDebug>()
+        .init_resource::<Occluders>()
+        .init_resource::<StaticGrid>()
+        .init_resource::<MovementParams>()
+        .init_resource::<TriggerOverlaps>()
+        .init_resource::<MotionTracker>()
+        .init_resource::<TrackerMaterials>()
+        .init_resource::<TrapInventory>()
+        .init_resource::<Alarm>()
+        .init_resource::<VisionDebug>()
+        .init_resource::<VisionDebugMaterial>()
+        .init_resource::<NoiseCueMaterial>()
+        .init_resource::<IntruderMaterial>()
+        .init_resource::<CameraController>()
+        .init_resource::<MinimapMaterials>()
+        .init_resource::<FogMaterials>()
+        .add_event::<DoorCommand>()
+        .add_event::<DoorChanged>()
+        .add_event::<IntruderStole>()
+        .add_event::<TrapTriggered>()
+        .add_event::<Collided>()
+        .add_event::<TriggerEntered>()
+        .add_event::<TriggerExited>()
+        .add_event::<NoiseEmitted>()
+        .add_event::<NoiseHeard>()
+        .add_event::<IntruderCaught>()
+        .add_event::<IntruderBrokeAway>()
+        .add_event::<ValuableStolen>()
         .add_startup_system(setup)
-        .add_startup_system(room::load_rooms.label("load_rooms"))
-        .add_startup_system(generate_world.after("load_rooms"))
+        .add_startup_system(setup_map_debug)
+        .add_startup_system(setup_door_console)
+        .add_startup_system(setup_monitor)
+        .add_startup_system(setup_traps)
+        .add_startup_system(setup_night_clock)
+        .add_startup_system(setup_stamina_bar)
+        .add_startup_system(load_rooms)
+        .add_startup_system(load_furniture)
+        .add_system_set(
+            SystemSet::on_enter(AppState::Playing)
+                .with_system(generate_world)
+                .with_system(cut_to_player),
+        )
         .add_system_set(
             SystemSet::new()
-                .with_run_criteria(FixedTimestep::step(1. / 60.))
+                .with_run_criteria(FixedTimestep::step(TIMESTEP).chain(playing))
                 .with_system(move_player.before("collision"))
-                .with_system(collision.label("collision"))
-                .with_system(move_camera.after("collision")),
+                .with_system(update_occluders.label("update_occluders"))
+                .with_system(
+                    sense_threats
+                        .label("sense_threats")
+                        .after("update_occluders"),
+                )
+                .with_system(
+                    update_behaviour
+                        .label("update_behaviour")
+                        .after("sense_threats"),
+                )
+                .with_system(
+                    act_on_behaviour
+                        .label("act_on_behaviour")
+                        .after("update_behaviour"),
+                )
+                .with_system(
+                    plan_intruder_paths
+                        .label("plan_intruder_paths")
+                        .after("update_behaviour"),
+                )
+                .with_system(
+                    move_intruders
+                        .after("plan_intruder_paths")
+                        .after("act_on_behaviour")
+                        .before("collision"),
+                )
+                .with_system(update_static_grid.label("update_static_grid"))
+                .with_system(collision.label("collision").after("update_static_grid"))
+                .with_system(stop_on_collision.after("collision"))
+                .with_system(confront_intruders.after("collision"))
+                .with_system(follow_player.after("collision")),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::Playing)
+                .with_system(door_console_input.label("door_console_input"))
+                .with_system(
+                    interact_doors
+                        .label("door_input")
+                        .after("door_console_input"),
+                )
+                .with_system(intruders_open_doors.before("apply_door_commands"))
+                .with_system(
+                    apply_door_commands
+                        .label("apply_door_commands")
+                        .after("door_input"),
+                )
+                .with_system(track_locked_doors.after("apply_door_commands"))
+                .with_system(update_door_sprites.after("apply_door_commands"))
+                .with_system(update_door_console)
+                .with_system(monitor_input.label("monitor_input"))
+                .with_system(camera_input)
+                .with_system(minimap_input.label("minimap_input").after("monitor_input"))
+                .with_system(track_sightings)
+                .with_system(update_fog.label("update_fog").after("monitor_input"))
+                .with_system(hide_intruders.after("update_fog"))
+                .with_system(render_minimap.after("minimap_input").after("update_fog"))
+                .with_system(monitor_view.after("monitor_input"))
+                .with_system(update_monitor_text.after("monitor_input"))
+                .with_system(attach_behaviour_labels)
+                .with_system(update_behaviour_labels)
+                .with_system(tracker_input.label("tracker_input").after("monitor_input"))
+                .with_system(sweep_tracker.label("sweep_tracker").after("tracker_input"))
+                .with_system(render_tracker.after("sweep_tracker"))
+                .with_system(place_traps)
+                .with_system(detect_triggers.label("detect_triggers"))
+                .with_system(
+                    trigger_traps
+                        .label("trigger_traps")
+                        .after("detect_triggers"),
+                )
+                .with_system(apply_trap_effects.after("trigger_traps"))
+                .with_system(expire_trap_effects)
+                .with_system(update_trap_text)
+                .with_system(emit_footsteps.before("propagate_noise"))
+                .with_system(
+                    emit_door_noise
+                        .before("propagate_noise")
+                        .after("apply_door_commands"),
+                )
+                .with_system(
+                    emit_trap_noise
+                        .before("propagate_noise")
+                        .after("trigger_traps"),
+                )
+                .with_system(propagate_noise.label("propagate_noise"))
+                .with_system(intruders_hear.after("propagate_noise"))
+                .with_system(show_noise_cues.after("propagate_noise"))
+                .with_system(fade_noise_cues)
+                .with_system(vision_debug_input.label("vision_debug_input"))
+                .with_system(vision_debug_render.after("vision_debug_input"))
+                .with_system(map_debug_input.label("map_debug_input"))
+                .with_system(map_debug_render.after("map_debug_input"))
+                .with_system(register_valuables)
+                .with_system(steal_valuables.label("steal_valuables"))
+                .with_system(
+                    advance_night
+                        .label("advance_night")
+                        .after("steal_valuables"),
+                )
+                .with_system(spawn_scheduled_intruders.after("advance_night"))
+                .with_system(update_night_clock.after("advance_night"))
+                .with_system(update_stamina_bar),
         )
-        .add_system(bevy::input::system::exit_on_esc_system)
         .run()
 }
 
-struct Player;
-struct MainCamera;
-
-#[derive(Default, Copy, Clone)]
-pub struct Collider {
-    size: Vec2,
-    offset: Vec3,
-}
-
-impl Collider {
-    pub fn new(size: Vec2, offset: Vec2) -> Self {
-        Self {
-            size,
-            offset: (offset, 0.).into(),
-        }
-    }
-}
-
-struct Nonstatic;
-
-fn setup(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+fn setup(mut commands: Commands) {
     commands
         .spawn_bundle(OrthographicCameraBundle::new_2d())
         .insert(MainCamera);
     commands
-        .spawn_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(16., 16.)),
-            material: materials.add(Color::GOLD.into()),
-            transform: Transform::from_xyz(10. * TILE_SIZE, 10. * TILE_SIZE, 1.),
-            ..Default::default()
-        })
-        .insert(Player)
-        .insert(Collider {
-            size: Vec2::new(16., 16.),
-            ..Default::default()
-        })
-        .insert(Nonstatic);
+        .spawn_bundle(OrthographicCameraBundle::new_2d())
+        .insert(MonitorCamera);
+    commands.spawn_bundle(UiCameraBundle::default());
 }
 
 fn generate_world(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    settings: Res<GameSettings>,
     rooms: Res<HashMap<RoomKind, Room>>,
+    furniture: Res<HashMap<FurnitureKind, Furniture>>,
+    door_materials: Res<DoorMaterials>,
+    fog_materials: Res<FogMaterials>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
+    let mut rng = StdRng::seed_from_u64(settings.night_seed());
+    let plan = NightPlan::new(&settings);
+
     // Randomize map
-    let mut map = Map::new(20, 20);
-    map.generate(&rooms);
+    let mut map = Map::new(plan.map_size, plan.map_size);
+    map.generate(&rooms, &mut rng);
 
     // Spawn entities for map
-    for room_kind in map.rooms {
-        rooms[&room_kind.0].spawn(&mut commands, room_kind.1 .0, room_kind.1 .1)
+    for room_kind in &map.rooms {
+        rooms[&room_kind.0].spawn(
+            &mut commands,
+            &furniture,
+            &mut rng,
+            room_kind.1 .0,
+            room_kind.1 .1,
+        )
     }
 
-    for (hallway_x, hallway_y) in map.hallways {
+    for &(hallway_x, hallway_y) in &map.hallways {
         rooms[&RoomKind::Hallway(map.occupied.hallway_kind(hallway_x, hallway_y))].spawn(
             &mut commands,
+            &furniture,
+            &mut rng,
             hallway_x,
             hallway_y,
         );
     }
 
-    // desk
-    commands
-        .spawn_bundle(SpriteBundle {
-            sprite: Sprite::new(Vec2::new(90., 100.)),
-            material: materials.add(asset_server.load("furniture/Security Desk.png").into()),
-            transform: Transform::from_xyz(0., 64., 0.),
-            ..Default::default()
-        })
-        .insert(Collider {
-            size: Vec2::new(90., 58.),
-            offset: Vec3::new(0., -21., 0.),
-        });
-    // doors
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(60., 80.)),
-        material: materials.add(asset_server.load("furniture/Door_open.png").into()),
-        transform: Transform::from_xyz(128., 64., 0.),
-        ..Default::default()
-    });
-    commands.spawn_bundle(SpriteBundle {
-        sprite: Sprite::new(Vec2::new(60., 80.)),
-        material: materials.add(asset_server.load("furniture/Door_closed.png").into()),
-        transform: Transform::from_xyz(192., 64., 0.),
-        ..Default::default()
-    });
-}
+    // intruders walk from the middle of one tile to the next, so they go around furniture
+    let blocked = map
+        .rooms
+        .iter()
+        .flat_map(|&(kind, (x, y))| rooms[&kind].blocked_tiles(&furniture, x, y))
+        .collect::<Vec<_>>();
+    for tile in blocked {
+        map.block(tile);
+    }
 
-fn move_player(input: Res<Input<KeyCode>>, mut query: Query<&mut Transform, With<Player>>) {
-    const PLAYER_SPEED: f32 = 2.;
-    if let Ok(mut player) = query.single_mut() {
-        // TODO: Normalize speed
-        if input.pressed(KeyCode::W) {
-            player.translation.y += PLAYER_SPEED;
-        } else if input.pressed(KeyCode::R) {
-            player.translation.y -= PLAYER_SPEED;
-        }
+    spawn_room_triggers(&mut commands, &map, &rooms);
 
-        if input.pressed(KeyCode::A) {
-            player.translation.x -= PLAYER_SPEED;
-        } else if input.pressed(KeyCode::S) {
-            player.translation.x += PLAYER_SPEED;
-        }
-    }
-}
+    let doors = spawn_doors(&mut commands, &map, &door_materials);
+    commands.insert_resource(doors);
 
-// FIXME: Assuming the nonstatic transform is relative to the reference frame may limit us in the
-// future.
-fn collision(
-    mut q0: Query<(&mut Transform, &Collider), With<Nonstatic>>,
-    q1: Query<(&GlobalTransform, &Collider), Without<Nonstatic>>,
-) {
-    use sprite::collide_aabb;
-    use sprite::collide_aabb::Collision;
-    for (mut tran, coll) in q0.iter_mut() {
-        for (static_tran, static_coll) in q1.iter() {
-            let collision = collide_aabb::collide(
-                tran.translation + coll.offset,
-                coll.size,
-                static_tran.translation + static_coll.offset,
-                static_coll.size,
-            );
+    let cameras = spawn_cameras(&mut commands, &map, materials.add(Color::DARK_GRAY.into()));
+    commands.insert_resource(cameras);
 
-            if let Some(side) = collision {
-                match side {
-                    Collision::Left => {
-                        tran.translation.x = static_tran.translation.x + static_coll.offset.x
-                            - (static_coll.size.x / 2.)
-                            - (coll.size.x / 2.);
-                    }
-                    Collision::Right => {
-                        tran.translation.x = static_tran.translation.x
-                            + static_coll.offset.x
-                            + (static_coll.size.x / 2.)
-                            + (coll.size.x / 2.);
-                    }
-                    Collision::Top => {
-                        tran.translation.y = static_tran.translation.y
-                            + static_coll.offset.y
-                            + (static_coll.size.y / 2.)
-                            + (coll.size.y / 2.);
-                    }
-                    Collision::Bottom => {
-                        tran.translation.y = static_tran.translation.y + static_coll.offset.y
-                            - (static_coll.size.y / 2.)
-                            - (coll.size.y / 2.);
-                    }
-                }
-            }
-        }
-    }
-}
+    let fog = spawn_fog(&mut commands, &map, &fog_materials);
+    commands.insert_resource(fog);
 
-fn move_camera(
-    mut camera_query: Query<&mut Transform, With<MainCamera>>,
-    player_query: Query<&GlobalTransform, With<Player>>,
-) {
-    let player = match player_query.single() {
-        Ok(p) => p,
-        Err(e) => {
-            error!("Player entity not found: {}", e);
-            return;
-        }
-    };
+    // the guard starts the shift in the middle of the security room
+    let (security_x, security_y) = map.rooms[0].1;
+    let security = &rooms[&RoomKind::Security];
+    let start = Vec2::new(
+        security_x as f32 + security.width as f32 / 2.,
+        security_y as f32 + security.height as f32 / 2.,
+    ) * TILE_SIZE;
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(16., 16.)),
+            material: materials.add(Color::GOLD.into()),
+            transform: Transform::from_xyz(start.x, start.y, 1.),
+            ..Default::default()
+        })
+        .insert(Player)
+        .insert(Velocity::default())
+        .insert(Gait::default())
+        .insert(Noise::default())
+        .insert(Stamina::default())
+        .insert(Listener)
+        .insert(Collider {
+            size: Vec2::new(16., 16.),
+            ..Default::default()
+        })
+        .insert(Nonstatic)
+        .insert(NightEntity);
 
-    let mut camera = match camera_query.single_mut() {
-        Ok(t) => t,
-        Err(e) => {
-            error!("Main Camera not found: {}", e);
-            return;
-        }
-    };
+    // nothing from the last night carries over
+    commands.insert_resource(LockedDoors::default());
+    commands.insert_resource(DoorConsole::default());
+    commands.insert_resource(Monitor::default());
+    commands.insert_resource(TriggerOverlaps::default());
+    commands.insert_resource(MotionTracker::default());
+    commands.insert_resource(TrapInventory::default());
+    commands.insert_resource(Alarm::default());
+    commands.insert_resource(NightReport::default());
+    commands.insert_resource(Valuables::default());
+    commands.insert_resource(Minimap::default());
+    commands.insert_resource(BehaviourTable::with_skill(plan.intruder_skill));
+    commands.insert_resource(plan);
 
-    camera.translation = player.translation;
+    commands.insert_resource(map);
 }
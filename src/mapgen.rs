@@ -1,31 +1,63 @@
 use crate::room::*;
 use pathfinding::prelude::*;
-use rand;
+use rand::seq::SliceRandom;
 use rand::Rng;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
 use std::fmt::Formatter;
 use std::ops::{Index, IndexMut};
 
+/// Rooms placed around the security room, and how often each kind turns up.
+const ROOM_WEIGHTS: [(RoomKind, u32); 3] = [
+    (RoomKind::Empty, 3),
+    (RoomKind::ServerRoom, 1),
+    (RoomKind::Vault, 1),
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TileKind {
     Empty,
+    /// A tile inside a room. The id indexes into `Map::rooms`, the same as `TriggerKind::Room` and
+    /// `MapDoor::room`. The security room is always 0.
     Room(usize),
-    Door,
+    /// A door tile inside `room`, opening onto the tile in the `facing` direction. `id` indexes
+    /// into `Map::doors`.
+    Door {
+        room: usize,
+        facing: Direction,
+        id: usize,
+    },
     Hallway,
 }
 
 impl TileKind {
-    fn connects(&self, other: &TileKind) -> bool {
+    /// Whether `self` connects to `other`, which lies in direction `dir` from `self`.
+    fn connects(&self, other: &TileKind, dir: Direction) -> bool {
         match *self {
             TileKind::Empty => false,
-            TileKind::Room(id) => *other == TileKind::Room(id) || *other == TileKind::Door,
-            TileKind::Door => *other != TileKind::Empty,
-            TileKind::Hallway => *other == TileKind::Hallway || *other == TileKind::Door,
+            TileKind::Room(id) => match *other {
+                TileKind::Room(other_id) => other_id == id,
+                TileKind::Door { room, .. } => room == id,
+                _ => false,
+            },
+            TileKind::Door { room, facing, .. } => match *other {
+                TileKind::Empty => false,
+                TileKind::Room(id) => id == room,
+                TileKind::Door {
+                    room: other_room, ..
+                } => other_room == room || dir == facing,
+                TileKind::Hallway => dir == facing,
+            },
+            TileKind::Hallway => match *other {
+                TileKind::Hallway => true,
+                TileKind::Door { facing, .. } => facing == dir.opposite(),
+                _ => false,
+            },
         }
     }
 
-    fn connects_hallway_pathing(&self, other: &TileKind) -> bool {
+    fn connects_hallway_pathing(&self, other: &TileKind, _dir: Direction) -> bool {
         match *self {
             TileKind::Empty => true,
             TileKind::Hallway => *other == TileKind::Hallway || *other == TileKind::Empty,
@@ -37,7 +69,7 @@ impl TileKind {
         match *self {
             TileKind::Empty => 100,
             TileKind::Room(_) => 1,
-            TileKind::Door => 1,
+            TileKind::Door { .. } => 1,
             TileKind::Hallway => 1,
         }
     }
@@ -50,7 +82,7 @@ pub struct TileArray {
 }
 
 impl TileArray {
-    fn new(width: usize, height: usize) -> Self {
+    pub fn new(width: usize, height: usize) -> Self {
         Self {
             inner: vec![TileKind::Empty; width * height],
             width,
@@ -58,7 +90,15 @@ impl TileArray {
         }
     }
 
-    fn iter(&self) -> impl Iterator<Item = (usize, usize, TileKind)> + '_ {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, TileKind)> + '_ {
         self.inner
             .iter()
             .enumerate()
@@ -68,19 +108,18 @@ impl TileArray {
     pub fn hallway_kind(&self, x: usize, y: usize) -> HallwayKind {
         // Choose cardinal neighbors that aren't out of bounds
         let neighbors = vec![
-            (Some(x), Some(y + 1)),
-            (Some(x + 1), Some(y)),
-            (Some(x), y.checked_sub(1)),
-            (x.checked_sub(1), Some(y)),
+            (Some(x), Some(y + 1), Direction::North),
+            (Some(x + 1), Some(y), Direction::East),
+            (Some(x), y.checked_sub(1), Direction::South),
+            (x.checked_sub(1), Some(y), Direction::West),
         ]
         .into_iter()
-        .map(|(x, y)| {
+        .map(|(x, y, dir)| {
             if let Some(x) = x {
                 if let Some(y) = y {
                     if x < self.width && y < self.height {
-                        if self[(x, y)] == TileKind::Hallway || self[(x, y)] == TileKind::Door {
-                            return true;
-                        }
+                        // doors only count when they open onto this tile
+                        return TileKind::Hallway.connects(&self[(x, y)], dir);
                     }
                 }
             }
@@ -117,7 +156,7 @@ impl TileArray {
         connectivity: F,
     ) -> Vec<((usize, usize, TileKind), usize)>
     where
-        F: Fn(&TileKind, &TileKind) -> bool,
+        F: Fn(&TileKind, &TileKind, Direction) -> bool,
     {
         {
             let tile = self[(x, y)];
@@ -125,26 +164,26 @@ impl TileArray {
 
             // Choose cardinal neighbors that aren't out of bounds
             vec![
-                (x.checked_sub(1), Some(y)),
-                (Some(x + 1), Some(y)),
-                (Some(x), y.checked_sub(1)),
-                (Some(x), Some(y + 1)),
+                (x.checked_sub(1), Some(y), Direction::West),
+                (Some(x + 1), Some(y), Direction::East),
+                (Some(x), y.checked_sub(1), Direction::South),
+                (Some(x), Some(y + 1), Direction::North),
             ]
             .into_iter()
-            .filter_map(|(x, y)| {
+            .filter_map(|(x, y, dir)| {
                 if let Some(x) = x {
                     if let Some(y) = y {
                         if x < self.width && y < self.height {
-                            return Some((x, y));
+                            return Some((x, y, dir));
                         }
                     }
                 }
                 None
             })
             // Check for connectivity to neighbors
-            .for_each(|(x, y)| {
+            .for_each(|(x, y, dir)| {
                 let neighbor_type = self[(x, y)];
-                if connectivity(&neighbor_type, &tile) {
+                if connectivity(&neighbor_type, &tile, dir.opposite()) {
                     adj.push(((x, y, neighbor_type), neighbor_type.weight()));
                 }
             });
@@ -196,29 +235,456 @@ impl IndexMut<(usize, usize)> for TileArray {
         &mut self.inner[index.1 * self.width + index.0]
     }
 }
+/// Union-find over tile indices, used to track connectivity incrementally as tiles are placed.
+struct DisjointSet {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+            size: vec![1; len],
+        }
+    }
+
+    fn find(&self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            i = self.parent[i];
+        }
+        i
+    }
+
+    /// Merge the sets containing `a` and `b`, returning `(root, absorbed_root)` if they were
+    /// separate.
+    fn union(&mut self, a: usize, b: usize) -> Option<(usize, usize)> {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return None;
+        }
+        // union by size keeps the trees shallow without needing path compression
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+        Some((a, b))
+    }
+}
+
 pub struct Map {
     pub occupied: TileArray,
     pub rooms: Vec<(RoomKind, (usize, usize))>,
     pub hallways: Vec<(usize, usize)>,
+    /// Every door on the map, indexed by the id stored in its `TileKind::Door`.
+    pub doors: Vec<MapDoor>,
+    pub cameras: Vec<MapCamera>,
+    pub history: Vec<GenStep>,
+    // tiles with furniture over the middle, which paths go around
+    blocked: HashSet<(usize, usize)>,
+    stage: GenStage,
+    // connectivity of every non-empty tile
+    sets: DisjointSet,
+    // one hallway tile for every set that contains hallways, keyed by set root
+    hallway_sets: BTreeMap<usize, (usize, usize)>,
+    // hallways that might still be dead ends
+    dead_ends: BTreeSet<(usize, usize)>,
     width: usize,
     height: usize,
 }
 
+/// A tile on the edge of spreading sound, ordered so the heap pops the nearest first.
+#[derive(PartialEq)]
+struct SoundFront(f32, (usize, usize));
+
+impl Eq for SoundFront {}
+
+impl Ord for SoundFront {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.partial_cmp(&self.0).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for SoundFront {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapDoor {
+    pub x: usize,
+    pub y: usize,
+    pub room: usize,
+    pub facing: Direction,
+}
+
+/// A security camera mounted on tile (`x`, `y`), looking along `facing` (radians, counterclockwise
+/// from east) with a field of view of `fov` radians.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapCamera {
+    pub x: usize,
+    pub y: usize,
+    pub facing: f32,
+    pub fov: f32,
+}
+
+/// A single observable step of map generation, along with the tiles it changed.
+#[derive(Debug, Clone)]
+pub struct GenStep {
+    pub kind: GenStepKind,
+    pub changed: Vec<(usize, usize, TileKind)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenStepKind {
+    SecurityRoom,
+    /// `attempt` counts every try, placed or not. It isn't a room id.
+    RoomAttempt {
+        attempt: usize,
+        x: usize,
+        y: usize,
+        placed: bool,
+    },
+    Connection,
+    DeadEndFix {
+        x: usize,
+        y: usize,
+    },
+    Cameras {
+        count: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GenStage {
+    SecurityRoom,
+    /// The next room placement attempt.
+    Rooms(usize),
+    Connections,
+    DeadEnds,
+    Cameras,
+    Done,
+}
+
 impl Map {
     pub fn new(width: usize, height: usize) -> Self {
         Self {
             occupied: TileArray::new(width, height),
             rooms: vec![],
             hallways: vec![],
+            doors: vec![],
+            cameras: vec![],
+            history: vec![],
+            blocked: HashSet::new(),
+            stage: GenStage::SecurityRoom,
+            sets: DisjointSet::new(width * height),
+            hallway_sets: BTreeMap::new(),
+            dead_ends: BTreeSet::new(),
             width,
             height,
         }
     }
 
-    pub fn generate(&mut self, rooms: &HashMap<RoomKind, Room>) {
-        let mut rng = rand::thread_rng();
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn generate<R: Rng>(&mut self, rooms: &HashMap<RoomKind, Room>, rng: &mut R) {
+        while self.step(rooms, rng).is_some() {}
+    }
+
+    /// Run the next step of generation, returning the recorded step or `None` once the map is
+    /// finished.
+    pub fn step<R: Rng>(
+        &mut self,
+        rooms: &HashMap<RoomKind, Room>,
+        rng: &mut R,
+    ) -> Option<&GenStep> {
+        let step = match self.stage {
+            GenStage::SecurityRoom => {
+                self.stage = GenStage::Rooms(1);
+                self.place_security_room(rooms)
+            }
+            GenStage::Rooms(attempt) => {
+                self.stage = if attempt < self.room_attempts() {
+                    GenStage::Rooms(attempt + 1)
+                } else {
+                    GenStage::Connections
+                };
+                self.place_random_room(rooms, attempt, rng)
+            }
+            GenStage::Connections => match self.connect_hallways() {
+                Some(step) => step,
+                None => {
+                    self.stage = GenStage::DeadEnds;
+                    return self.step(rooms, rng);
+                }
+            },
+            GenStage::DeadEnds => match self.fix_dead_end() {
+                Some(step) => step,
+                None => {
+                    self.stage = GenStage::Cameras;
+                    return self.step(rooms, rng);
+                }
+            },
+            GenStage::Cameras => {
+                self.stage = GenStage::Done;
+                self.place_cameras(rooms)
+            }
+            GenStage::Done => return None,
+        };
+
+        self.history.push(step);
+        self.history.last()
+    }
+
+    /// Number of random room placements to try, scaled with the map area.
+    fn room_attempts(&self) -> usize {
+        (self.width * self.height / 50).max(1)
+    }
+
+    /// Rebuild the tiles as they were after the first `steps` steps of the history.
+    pub fn replay(&self, steps: usize) -> TileArray {
+        let mut tiles = TileArray::new(self.width, self.height);
+        for step in self.history.iter().take(steps) {
+            for &(x, y, tile) in &step.changed {
+                tiles[(x, y)] = tile;
+            }
+        }
+        tiles
+    }
+
+    fn set_tile(
+        &mut self,
+        x: usize,
+        y: usize,
+        tile: TileKind,
+        changed: &mut Vec<(usize, usize, TileKind)>,
+    ) {
+        if self.occupied[(x, y)] == tile {
+            return;
+        }
+        self.occupied[(x, y)] = tile;
+        changed.push((x, y, tile));
+
+        let index = y * self.width + x;
+        if tile == TileKind::Hallway {
+            self.hallways.push((x, y));
+            self.hallway_sets
+                .entry(self.sets.find(index))
+                .or_insert((x, y));
+            self.dead_ends.insert((x, y));
+        }
+
+        for ((nx, ny, _), _) in self.occupied.get_connections(x, y, TileKind::connects) {
+            self.join(index, ny * self.width + nx);
+        }
+    }
+
+    fn place_door(
+        &mut self,
+        x: usize,
+        y: usize,
+        room: usize,
+        facing: Direction,
+        changed: &mut Vec<(usize, usize, TileKind)>,
+    ) {
+        let id = self.doors.len();
+        self.doors.push(MapDoor { x, y, room, facing });
+        self.set_tile(x, y, TileKind::Door { room, facing, id }, changed);
+    }
+
+    /// Look up the door with the given id.
+    pub fn door(&self, id: usize) -> Option<&MapDoor> {
+        self.doors.get(id)
+    }
+
+    /// Index into `rooms` of the room `tile` is in, doorways included.
+    pub fn room_at(&self, tile: (usize, usize)) -> Option<usize> {
+        match self.occupied[tile] {
+            TileKind::Room(room) | TileKind::Door { room, .. } => Some(room),
+            TileKind::Empty | TileKind::Hallway => None,
+        }
+    }
+
+    /// The tile containing the world position (`x`, `y`), if it's on the map.
+    pub fn world_to_tile(&self, x: f32, y: f32) -> Option<(usize, usize)> {
+        if x < 0. || y < 0. {
+            return None;
+        }
+        let (tx, ty) = ((x / TILE_SIZE) as usize, (y / TILE_SIZE) as usize);
+        if tx < self.width && ty < self.height {
+            Some((tx, ty))
+        } else {
+            None
+        }
+    }
+
+    /// The hallway tile closest to the edge of the map, where intruders break in.
+    pub fn entrance(&self) -> Option<(usize, usize)> {
+        self.hallways
+            .iter()
+            .copied()
+            .min_by_key(|&(x, y)| x.min(y).min(self.width - 1 - x).min(self.height - 1 - y))
+    }
+
+    /// Keep paths from going through `tile`, because something is in the way in the middle of it.
+    /// Sound still goes through.
+    pub fn block(&mut self, tile: (usize, usize)) {
+        self.blocked.insert(tile);
+    }
+
+    pub fn is_blocked(&self, tile: (usize, usize)) -> bool {
+        self.blocked.contains(&tile)
+    }
+
+    /// Shortest walkable path between two tiles, going through rooms, doors and hallways.
+    /// Doors for which `locked` returns true and blocked tiles can't be walked through.
+    pub fn find_path<F>(
+        &self,
+        from: (usize, usize),
+        to: (usize, usize),
+        locked: F,
+    ) -> Option<Vec<(usize, usize)>>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let path = astar(
+            &from,
+            |&tile| self.passable_neighbors(tile, &locked),
+            |&(x, y)| {
+                (x as isize - to.0 as isize).abs() as usize
+                    + (y as isize - to.1 as isize).abs() as usize
+            },
+            |&tile| tile == to,
+        );
+
+        path.map(|(tiles, _)| tiles)
+    }
+
+    /// Every tile within `max_steps` steps of `from`, along with how many steps away it is,
+    /// without going through blocked tiles.
+    pub fn reachable<F>(
+        &self,
+        from: (usize, usize),
+        max_steps: usize,
+        locked: F,
+    ) -> Vec<((usize, usize), usize)>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let mut seen = HashMap::new();
+        let mut queue = VecDeque::new();
+        seen.insert(from, 0);
+        queue.push_back((from, 0));
+        while let Some((tile, steps)) = queue.pop_front() {
+            if steps == max_steps {
+                continue;
+            }
+            for (next, _) in self.passable_neighbors(tile, &locked) {
+                if !seen.contains_key(&next) {
+                    seen.insert(next, steps + 1);
+                    queue.push_back((next, steps + 1));
+                }
+            }
+        }
+        seen.into_iter().collect()
+    }
+
+    /// How far sound from `from` travels to reach every tile, in tiles, going around walls rather
+    /// than through them. Going through a door adds `door_cost(id)` on top so closed doors can
+    /// muffle it. Tiles further than `max_cost` are left out.
+    pub fn sound_distances<F>(
+        &self,
+        from: (usize, usize),
+        max_cost: f32,
+        door_cost: F,
+    ) -> HashMap<(usize, usize), f32>
+    where
+        F: Fn(usize) -> f32,
+    {
+        let mut costs = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        costs.insert(from, 0.);
+        queue.push(SoundFront(0., from));
+        while let Some(SoundFront(cost, tile)) = queue.pop() {
+            if cost > costs[&tile] {
+                continue;
+            }
+            // sound goes through locked doors too, just quieter
+            for (next, _) in self.walkable_neighbors(tile, &|_| false) {
+                let step = match self.occupied[next] {
+                    TileKind::Door { id, .. } => 1. + door_cost(id),
+                    _ => 1.,
+                };
+                let next_cost = cost + step;
+                if next_cost <= max_cost && costs.get(&next).map_or(true, |&c| next_cost < c) {
+                    costs.insert(next, next_cost);
+                    queue.push(SoundFront(next_cost, next));
+                }
+            }
+        }
+        costs
+    }
+
+    /// Tiles you can step to from `tile`, ignoring whether doors are locked.
+    pub fn connected_neighbors(&self, tile: (usize, usize)) -> Vec<(usize, usize)> {
+        self.walkable_neighbors(tile, &|_| false)
+            .into_iter()
+            .map(|(next, _)| next)
+            .collect()
+    }
+
+    /// Walkable neighbors that something can actually walk to the middle of.
+    fn passable_neighbors<F>(
+        &self,
+        tile: (usize, usize),
+        locked: &F,
+    ) -> Vec<((usize, usize), usize)>
+    where
+        F: Fn(usize) -> bool,
+    {
+        let mut neighbors = self.walkable_neighbors(tile, locked);
+        neighbors.retain(|(next, _)| !self.blocked.contains(next));
+        neighbors
+    }
+
+    fn walkable_neighbors<F>(
+        &self,
+        (x, y): (usize, usize),
+        locked: &F,
+    ) -> Vec<((usize, usize), usize)>
+    where
+        F: Fn(usize) -> bool,
+    {
+        self.occupied
+            .get_connections(x, y, TileKind::connects)
+            .into_iter()
+            .filter(|&((_, _, tile), _)| match tile {
+                TileKind::Door { id, .. } => !locked(id),
+                _ => true,
+            })
+            .map(|((x, y, _), weight)| ((x, y), weight))
+            .collect()
+    }
+
+    fn join(&mut self, a: usize, b: usize) {
+        if let Some((root, absorbed)) = self.sets.union(a, b) {
+            if let Some(tile) = self.hallway_sets.remove(&absorbed) {
+                self.hallway_sets.entry(root).or_insert(tile);
+            }
+        }
+    }
+
+    fn place_security_room(&mut self, rooms: &HashMap<RoomKind, Room>) -> GenStep {
+        let mut changed = Vec::new();
 
-        // place security room
         let security_room = &rooms[&RoomKind::Security];
         let (sec_x, sec_y) = (
             self.width / 2 - security_room.width / 2,
@@ -228,158 +694,210 @@ impl Map {
         self.rooms.push((RoomKind::Security, (sec_x, sec_y)));
         for y in sec_y..security_room.height + sec_y {
             for x in sec_x..security_room.width + sec_x {
-                self.occupied[(x, y)] = TileKind::Room(0);
+                self.set_tile(x, y, TileKind::Room(0), &mut changed);
             }
         }
-        for (dx, dy, _) in room_doors(security_room, sec_x, sec_y) {
-            self.occupied[(dx, dy)] = TileKind::Door;
+        for (dx, dy, dir) in room_doors(security_room, sec_x, sec_y) {
+            self.place_door(dx, dy, 0, dir, &mut changed);
         }
 
-        let hallways = room_adjacent_hallways(security_room, sec_x, sec_y);
-        for (x, y) in hallways {
-            self.hallways.push((x, y));
-            self.occupied[(x, y)] = TileKind::Hallway;
+        for (x, y) in room_adjacent_hallways(security_room, sec_x, sec_y) {
+            self.set_tile(x, y, TileKind::Hallway, &mut changed);
         }
 
-        // place random rooms
-        for id in 1..=8 {
-            // pick room (only one option for now)
-            let room = &rooms[&RoomKind::Empty];
+        GenStep {
+            kind: GenStepKind::SecurityRoom,
+            changed,
+        }
+    }
 
-            let room_x = rng.gen_range(1..self.width - room.width);
-            let room_y = rng.gen_range(1..self.height - room.height);
+    fn place_random_room<R: Rng>(
+        &mut self,
+        rooms: &HashMap<RoomKind, Room>,
+        attempt: usize,
+        rng: &mut R,
+    ) -> GenStep {
+        let mut changed = Vec::new();
 
-            let hallways = room_adjacent_hallways(room, room_x, room_y);
+        let kind = ROOM_WEIGHTS
+            .choose_weighted(rng, |(_, weight)| *weight)
+            .unwrap()
+            .0;
+        let room = &rooms[&kind];
 
-            // Check target positions
-            let mut blocked = false;
-            for y in room_y..room.height + room_y {
-                for x in room_x..room.width + room_x {
-                    if self.occupied[(x, y)] != TileKind::Empty {
-                        blocked = true;
-                    }
-                }
-            }
-            for (x, y) in hallways.clone() {
+        let room_x = rng.gen_range(1..self.width - room.width);
+        let room_y = rng.gen_range(1..self.height - room.height);
+
+        let hallways = room_adjacent_hallways(room, room_x, room_y);
+
+        // Check target positions
+        let mut blocked = false;
+        for y in room_y..room.height + room_y {
+            for x in room_x..room.width + room_x {
                 if self.occupied[(x, y)] != TileKind::Empty {
                     blocked = true;
-                    break;
                 }
             }
-            if blocked {
-                continue;
+        }
+        for (x, y) in hallways.clone() {
+            if self.occupied[(x, y)] != TileKind::Empty {
+                blocked = true;
+                break;
             }
+        }
 
-            // actully place room and hallways
-            self.rooms.push((RoomKind::Empty, (room_x, room_y)));
+        if !blocked {
+            // actully place room and hallways, failed attempts don't use up an id
+            let id = self.rooms.len();
+            self.rooms.push((kind, (room_x, room_y)));
             for y in room_y..room.height + room_y {
                 for x in room_x..room.width + room_x {
-                    self.occupied[(x, y)] = TileKind::Room(id);
+                    self.set_tile(x, y, TileKind::Room(id), &mut changed);
                 }
             }
-            for (dx, dy, _) in room_doors(room, room_x, room_y) {
-                self.occupied[(dx, dy)] = TileKind::Door;
+            for (dx, dy, dir) in room_doors(room, room_x, room_y) {
+                self.place_door(dx, dy, id, dir, &mut changed);
             }
 
             for (x, y) in hallways {
-                self.hallways.push((x, y));
-                self.occupied[(x, y)] = TileKind::Hallway;
+                self.set_tile(x, y, TileKind::Hallway, &mut changed);
             }
         }
 
-        // place hallways
-        // 1. determine connectivity and create sets
-        // 2. connect unconnected sets, thus merging them
-        // 3. profit
-        let mut connected_hallways = self.connected_hallways();
-
-        while connected_hallways.len() > 1 {
-            //Connect set 0 and 1
-
-            let path = dijkstra(
-                &connected_hallways[0][0],
-                |&(x, y, _)| {
-                    self.occupied
-                        .get_connections(x, y, TileKind::connects_hallway_pathing)
-                },
-                |&t| {
-                    t == connected_hallways[1][0]
-                        || (t.2 == TileKind::Hallway && !connected_hallways[0].contains(&t))
-                },
-            );
-
-            if let Some(path) = path {
-                for (x, y, _) in path.0 {
-                    self.occupied[(x, y)] = TileKind::Hallway;
-                    self.hallways.push((x, y));
-                }
-            } else {
-                panic!("Unconnectable hallway in mapgen");
+        GenStep {
+            kind: GenStepKind::RoomAttempt {
+                attempt,
+                x: room_x,
+                y: room_y,
+                placed: !blocked,
+            },
+            changed,
+        }
+    }
+
+    // place hallways
+    // 1. determine connectivity and create sets
+    // 2. connect unconnected sets, thus merging them
+    // 3. profit
+    fn connect_hallways(&mut self) -> Option<GenStep> {
+        if self.hallway_sets.len() <= 1 {
+            return None;
+        }
+
+        // Path out of the smallest set so the search doesn't have to flood the main building
+        let (&root, &(x, y)) = self
+            .hallway_sets
+            .iter()
+            .min_by_key(|(&root, _)| self.sets.size[root])?;
+
+        let path = dijkstra(
+            &(x, y, TileKind::Hallway),
+            |&(x, y, _)| {
+                self.occupied
+                    .get_connections(x, y, TileKind::connects_hallway_pathing)
+            },
+            |&(x, y, tile)| tile == TileKind::Hallway && self.sets.find(y * self.width + x) != root,
+        );
+
+        let mut changed = Vec::new();
+        if let Some(path) = path {
+            for (x, y, _) in path.0 {
+                self.set_tile(x, y, TileKind::Hallway, &mut changed);
             }
-            connected_hallways = self.connected_hallways();
-        }
-
-        // connect hallways with <2 neighbors to nearest hallway
-        let mut singles = self.get_single_hallways();
-        while singles.len() > 0 {
-            let path = dijkstra(
-                &singles[0],
-                |&(x, y, _)| {
-                    self.occupied
-                        .get_connections(x, y, TileKind::connects_hallway_pathing)
-                },
-                |&t| t.2 == TileKind::Hallway && t != singles[0],
-            );
-
-            if let Some(path) = path {
-                for (x, y, _) in path.0 {
-                    self.occupied[(x, y)] = TileKind::Hallway;
-                    self.hallways.push((x, y));
-                }
-            } else {
-                panic!("Unconnectable hallway in mapgen");
+        } else {
+            panic!("Unconnectable hallway in mapgen");
+        }
+
+        Some(GenStep {
+            kind: GenStepKind::Connection,
+            changed,
+        })
+    }
+
+    fn place_cameras(&mut self, rooms: &HashMap<RoomKind, Room>) -> GenStep {
+        use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+        // one camera in the top left corner of every room, looking across it
+        for (kind, (x, y)) in &self.rooms {
+            self.cameras.push(MapCamera {
+                x: *x,
+                y: y + rooms[kind].height - 1,
+                facing: -FRAC_PI_4,
+                fov: FRAC_PI_2,
+            });
+        }
+
+        // and some on hallway junctions, spaced out so they don't all watch the same spot
+        let first_hallway_camera = self.cameras.len();
+        for &(x, y) in &self.hallways {
+            let connections = self.occupied.get_connections(x, y, TileKind::connects);
+            if connections.len() < 3 {
+                continue;
             }
-            singles = self.get_single_hallways();
+            let crowded = self.cameras[first_hallway_camera..]
+                .iter()
+                .any(|c| (c.x as isize - x as isize).abs() + (c.y as isize - y as isize).abs() < 6);
+            if crowded {
+                continue;
+            }
+
+            let ((nx, ny, _), _) = connections[0];
+            self.cameras.push(MapCamera {
+                x,
+                y,
+                facing: (ny as f32 - y as f32).atan2(nx as f32 - x as f32),
+                fov: FRAC_PI_2 * 0.8,
+            });
+        }
+
+        GenStep {
+            kind: GenStepKind::Cameras {
+                count: self.cameras.len(),
+            },
+            changed: Vec::new(),
         }
     }
 
-    fn get_single_hallways(&self) -> Vec<(usize, usize, TileKind)> {
-        self.hallways
-            .iter()
-            .filter_map(|(x, y)| match self.occupied.hallway_kind(*x, *y) {
+    // connect hallways with <2 neighbors to nearest hallway
+    fn fix_dead_end(&mut self) -> Option<GenStep> {
+        // Hallways only ever gain neighbors, so anything that stopped being a dead end can be
+        // dropped for good
+        let single = loop {
+            let (x, y) = *self.dead_ends.iter().next()?;
+            self.dead_ends.remove(&(x, y));
+            match self.occupied.hallway_kind(x, y) {
                 HallwayKind::North | HallwayKind::East | HallwayKind::South | HallwayKind::West => {
-                    Some((*x, *y, TileKind::Hallway))
+                    break (x, y, TileKind::Hallway)
                 }
-                _ => None,
-            })
-            .collect::<Vec<_>>()
-    }
+                _ => {}
+            }
+        };
 
-    fn connected_hallways(&self) -> Vec<Vec<(usize, usize, TileKind)>> {
-        let components = connected_components(
-            &self
-                .occupied
-                .iter()
-                .filter(|&(_, _, tile)| tile != TileKind::Empty)
-                .collect::<Vec<_>>(),
+        let path = dijkstra(
+            &single,
             |&(x, y, _)| {
                 self.occupied
-                    .get_connections(x, y, TileKind::connects)
-                    .iter()
-                    .map(|(a, _)| *a)
-                    .collect::<Vec<_>>()
+                    .get_connections(x, y, TileKind::connects_hallway_pathing)
             },
+            |&t| t.2 == TileKind::Hallway && t != single,
         );
 
-        // filter for Hallways
-        components
-            .into_iter()
-            .map(|set| {
-                set.into_iter()
-                    .filter(|(_, _, tile)| *tile == TileKind::Hallway)
-                    .collect::<Vec<(usize, usize, TileKind)>>()
-            })
-            .collect::<Vec<_>>()
+        let mut changed = Vec::new();
+        if let Some(path) = path {
+            for (x, y, _) in path.0 {
+                self.set_tile(x, y, TileKind::Hallway, &mut changed);
+            }
+        } else {
+            panic!("Unconnectable hallway in mapgen");
+        }
+
+        Some(GenStep {
+            kind: GenStepKind::DeadEndFix {
+                x: single.0,
+                y: single.1,
+            },
+            changed,
+        })
     }
 }
 
@@ -387,7 +905,7 @@ fn room_doors(
     room: &Room,
     room_x: usize,
     room_y: usize,
-) -> impl Iterator<Item = (usize, usize, crate::room::Direction)> + Clone + '_ {
+) -> impl Iterator<Item = (usize, usize, Direction)> + Clone + '_ {
     room.doors
         .iter()
         .map(move |(dx, dy, dir)| (dx + room_x, dy + room_y, *dir))
@@ -399,9 +917,115 @@ fn room_adjacent_hallways(
     room_y: usize,
 ) -> impl Iterator<Item = (usize, usize)> + Clone + '_ {
     room_doors(room, room_x, room_y).map(move |(dx, dy, dir)| match dir {
-        crate::room::Direction::North => (dx, dy + 1),
-        crate::room::Direction::East => (dx + 1, dy),
-        crate::room::Direction::South => (dx, dy - 1),
-        crate::room::Direction::West => (dx - 1, dy),
+        Direction::North => (dx, dy + 1),
+        Direction::East => (dx + 1, dy),
+        Direction::South => (dx, dy - 1),
+        Direction::West => (dx - 1, dy),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::furniture::new_furniture;
+    use bevy::prelude::Handle;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const SIZES: [usize; 4] = [16, 20, 26, 40];
+    const SEEDS: u64 = 10;
+
+    fn generated_maps() -> impl Iterator<Item = Map> {
+        let rooms = new_rooms(|_| Handle::default());
+        SIZES.iter().flat_map(move |&size| {
+            let rooms = &rooms;
+            (0..SEEDS)
+                .map(move |seed| {
+                    let mut map = Map::new(size, size);
+                    map.generate(rooms, &mut StdRng::seed_from_u64(seed));
+                    map
+                })
+                .collect::<Vec<_>>()
+        })
+    }
+
+    #[test]
+    fn every_tile_is_reachable() {
+        for map in generated_maps() {
+            let start = map.rooms[0].1;
+            let reached = map.reachable(start, usize::MAX, |_| false);
+            let walkable = map
+                .occupied
+                .iter()
+                .filter(|&(_, _, tile)| tile != TileKind::Empty)
+                .count();
+            assert_eq!(reached.len(), walkable, "\n{:?}", map.occupied);
+        }
+    }
+
+    #[test]
+    fn furniture_leaves_every_tile_reachable() {
+        let rooms = new_rooms(|_| Handle::default());
+        let furniture = new_furniture(|_| Handle::default());
+        for mut map in generated_maps() {
+            let blocked = map
+                .rooms
+                .iter()
+                .flat_map(|&(kind, (x, y))| rooms[&kind].blocked_tiles(&furniture, x, y))
+                .collect::<HashSet<_>>();
+            // the desks are over the middle of some tiles, so there's always something
+            assert!(!blocked.is_empty());
+            for &tile in &blocked {
+                map.block(tile);
+            }
+
+            let start = map.rooms[0].1;
+            let reached = map.reachable(start, usize::MAX, |_| false);
+            assert!(reached.iter().all(|(tile, _)| !blocked.contains(tile)));
+            let walkable = map
+                .occupied
+                .iter()
+                .filter(|&(x, y, tile)| tile != TileKind::Empty && !blocked.contains(&(x, y)))
+                .count();
+            assert_eq!(reached.len(), walkable, "\n{:?}", map.occupied);
+        }
+    }
+
+    #[test]
+    fn no_dead_end_hallways() {
+        for map in generated_maps() {
+            for &(x, y) in &map.hallways {
+                let exits = map.occupied.get_connections(x, y, TileKind::connects).len();
+                assert!(exits >= 2, "dead end at {:?}\n{:?}", (x, y), map.occupied);
+            }
+        }
+    }
+
+    #[test]
+    fn room_ids_index_rooms() {
+        let rooms = new_rooms(|_| Handle::default());
+        for map in generated_maps() {
+            // most maps have failed attempts, which used to throw the ids off
+            for (x, y, tile) in map.occupied.iter() {
+                let id = match tile {
+                    TileKind::Room(id) | TileKind::Door { room: id, .. } => id,
+                    _ => continue,
+                };
+                let (kind, (room_x, room_y)) = map.rooms[id];
+                let room = &rooms[&kind];
+                assert!((room_x..room_x + room.width).contains(&x));
+                assert!((room_y..room_y + room.height).contains(&y));
+            }
+            for door in &map.doors {
+                assert_eq!(
+                    map.occupied[(door.x, door.y)],
+                    TileKind::Door {
+                        room: door.room,
+                        facing: door.facing,
+                        id: map.doors.iter().position(|d| d == door).unwrap(),
+                    }
+                );
+            }
+        }
+    }
+}
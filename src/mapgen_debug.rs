@@ -0,0 +1,169 @@
+use bevy::prelude::*;
+use rusty_jam::input::{Action, ActionState};
+use rusty_jam::mapgen::*;
+use rusty_jam::room::{tile_to_world, TILE_SIZE};
+use rusty_jam::state::NightEntity;
+
+/// Steps through the recorded mapgen history.
+///
+/// F1 toggles the overlay, Left/Right step backwards and forwards, Home/End jump to either end.
+#[derive(Default)]
+pub struct MapDebug {
+    enabled: bool,
+    cursor: usize,
+}
+
+pub struct MapDebugMaterials {
+    room: Handle<ColorMaterial>,
+    door: Handle<ColorMaterial>,
+    hallway: Handle<ColorMaterial>,
+    changed: Handle<ColorMaterial>,
+}
+
+impl FromWorld for MapDebugMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        Self {
+            room: materials.add(Color::rgba(0.2, 0.4, 0.9, 0.6).into()),
+            door: materials.add(Color::rgba(0.9, 0.8, 0.1, 0.8).into()),
+            hallway: materials.add(Color::rgba(0.6, 0.6, 0.6, 0.6).into()),
+            changed: materials.add(Color::rgba(1.0, 0.1, 0.1, 0.8).into()),
+        }
+    }
+}
+
+struct MapDebugTile;
+struct MapDebugLabel;
+
+pub fn setup_map_debug(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(5.),
+                    left: Val::Px(5.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+                    font_size: 20.,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(MapDebugLabel);
+}
+
+pub fn map_debug_input(
+    input: Res<Input<KeyCode>>,
+    actions: Res<ActionState>,
+    map: Res<Map>,
+    mut debug: ResMut<MapDebug>,
+) {
+    if actions.just_pressed(Action::MapDebug) {
+        debug.enabled = !debug.enabled;
+        debug.cursor = 0;
+    }
+
+    if !debug.enabled {
+        return;
+    }
+
+    let steps = map.history.len();
+    // each night's map has a history of its own
+    if map.is_changed() && debug.cursor > steps {
+        debug.cursor = steps;
+    }
+    if input.just_pressed(KeyCode::Right) {
+        debug.cursor = (debug.cursor + 1).min(steps);
+    } else if input.just_pressed(KeyCode::Left) {
+        debug.cursor = debug.cursor.saturating_sub(1);
+    } else if input.just_pressed(KeyCode::Home) {
+        debug.cursor = 0;
+    } else if input.just_pressed(KeyCode::End) {
+        debug.cursor = steps;
+    }
+}
+
+pub fn map_debug_render(
+    mut commands: Commands,
+    debug: Res<MapDebug>,
+    map: Res<Map>,
+    materials: Res<MapDebugMaterials>,
+    tile_query: Query<Entity, With<MapDebugTile>>,
+    mut label_query: Query<&mut Text, With<MapDebugLabel>>,
+) {
+    // a new night brings a new map to show
+    if !debug.is_changed() && !map.is_changed() {
+        return;
+    }
+
+    for entity in tile_query.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let mut label = match label_query.single_mut() {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Map debug label not found: {}", e);
+            return;
+        }
+    };
+
+    if !debug.enabled {
+        label.sections[0].value.clear();
+        return;
+    }
+
+    // tiles touched by the step we're currently looking at
+    let current = debug.cursor.checked_sub(1).and_then(|i| map.history.get(i));
+    let changed = current
+        .map(|step| {
+            step.changed
+                .iter()
+                .map(|&(x, y, _)| (x, y))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    for (x, y, tile) in map.replay(debug.cursor).iter() {
+        let material = if changed.contains(&(x, y)) {
+            materials.changed.clone()
+        } else {
+            match tile {
+                TileKind::Empty => continue,
+                TileKind::Room(_) => materials.room.clone(),
+                TileKind::Door { .. } => materials.door.clone(),
+                TileKind::Hallway => materials.hallway.clone(),
+            }
+        };
+
+        let position = tile_to_world(x, y);
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite::new(Vec2::new(TILE_SIZE - 4., TILE_SIZE - 4.)),
+                material,
+                transform: Transform::from_xyz(position.x, position.y, 10.),
+                ..Default::default()
+            })
+            .insert(MapDebugTile)
+            .insert(NightEntity);
+    }
+
+    label.sections[0].value = match current {
+        Some(step) => format!(
+            "mapgen step {}/{}: {:?}",
+            debug.cursor,
+            map.history.len(),
+            step.kind
+        ),
+        None => format!("mapgen step 0/{}", map.history.len()),
+    };
+}
@@ -0,0 +1,290 @@
+use crate::behaviour::Senses;
+use crate::catch::IntruderCaught;
+use crate::door::LockedDoors;
+use crate::fog::Fog;
+use crate::input::{Action, ActionState};
+use crate::intruder::Intruder;
+use crate::mapgen::{Map, TileKind};
+use crate::room::{RoomKind, TILE_SIZE};
+use crate::security_camera::Monitor;
+use crate::state::NightEntity;
+use crate::trap::Revealed;
+use crate::Player;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+/// Width and height of the corner minimap, in pixels.
+const MINIMAP_SIZE: f32 = 160.;
+/// How much of the screen the overview map fills.
+const OVERVIEW_FILL: f32 = 0.9;
+/// Smallest a marker gets drawn, however small the tiles are.
+const MIN_MARKER_SIZE: f32 = 4.;
+/// Seconds between redraws. The map is rebuilt from scratch, so it isn't done every frame.
+const REFRESH_SECONDS: f32 = 0.25;
+
+/// The map of the building in the corner of the screen, and the full size one on the wall of the
+/// security room. `Overview` opens the full size one while the guard is in there.
+pub struct Minimap {
+    pub overview: bool,
+    /// Last tile each intruder was seen on, by the guard, a camera or a trap.
+    pub last_seen: HashMap<Entity, (usize, usize)>,
+    timer: Timer,
+}
+
+impl Default for Minimap {
+    fn default() -> Self {
+        Self {
+            overview: false,
+            last_seen: HashMap::new(),
+            timer: Timer::from_seconds(REFRESH_SECONDS, true),
+        }
+    }
+}
+
+pub struct MinimapMaterials {
+    clear: Handle<ColorMaterial>,
+    shade: Handle<ColorMaterial>,
+    background: Handle<ColorMaterial>,
+    room: Handle<ColorMaterial>,
+    security: Handle<ColorMaterial>,
+    hallway: Handle<ColorMaterial>,
+    door_open: Handle<ColorMaterial>,
+    door_locked: Handle<ColorMaterial>,
+    camera: Handle<ColorMaterial>,
+    guard: Handle<ColorMaterial>,
+    intruder: Handle<ColorMaterial>,
+}
+
+impl FromWorld for MinimapMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        Self {
+            clear: materials.add(Color::NONE.into()),
+            shade: materials.add(Color::rgba(0.0, 0.0, 0.0, 0.85).into()),
+            background: materials.add(Color::rgba(0.0, 0.0, 0.1, 0.8).into()),
+            room: materials.add(Color::rgb(0.2, 0.4, 0.9).into()),
+            security: materials.add(Color::rgb(0.2, 0.7, 0.9).into()),
+            hallway: materials.add(Color::rgb(0.5, 0.5, 0.5).into()),
+            door_open: materials.add(Color::rgb(0.3, 0.9, 0.3).into()),
+            door_locked: materials.add(Color::rgb(0.9, 0.2, 0.2).into()),
+            camera: materials.add(Color::WHITE.into()),
+            guard: materials.add(Color::GOLD.into()),
+            intruder: materials.add(Color::CRIMSON.into()),
+        }
+    }
+}
+
+struct MinimapNode;
+
+fn in_security_room(map: &Map, position: Vec3) -> bool {
+    map.world_to_tile(position.x, position.y)
+        .and_then(|tile| map.room_at(tile))
+        .map_or(false, |room| map.rooms[room].0 == RoomKind::Security)
+}
+
+pub fn minimap_input(
+    actions: Res<ActionState>,
+    map: Res<Map>,
+    monitor: Res<Monitor>,
+    mut minimap: ResMut<Minimap>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+) {
+    let player = match player_query.single() {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    // the overview is on the security room wall, and the monitor takes over the screen
+    if monitor.active || !in_security_room(&map, player.translation) {
+        if minimap.overview {
+            minimap.overview = false;
+        }
+        return;
+    }
+
+    if actions.just_pressed(Action::Overview) {
+        minimap.overview = !minimap.overview;
+    }
+}
+
+/// Remember where intruders were last spotted, and forget the ones that got caught.
+pub fn track_sightings(
+    map: Res<Map>,
+    mut minimap: ResMut<Minimap>,
+    mut caught: EventReader<IntruderCaught>,
+    intruder_query: Query<(Entity, &GlobalTransform, &Senses, Option<&Revealed>), With<Intruder>>,
+) {
+    for event in caught.iter() {
+        minimap.last_seen.remove(&event.intruder);
+    }
+
+    for (entity, tran, senses, revealed) in intruder_query.iter() {
+        if !senses.seen && revealed.is_none() {
+            continue;
+        }
+        if let Some(tile) = map.world_to_tile(tran.translation.x, tran.translation.y) {
+            if minimap.last_seen.get(&entity) != Some(&tile) {
+                minimap.last_seen.insert(entity, tile);
+            }
+        }
+    }
+}
+
+/// Rebuild the minimap, or the overview while it's open. Rows of matching tiles are drawn as one
+/// node to keep the node count down on big maps.
+pub fn render_minimap(
+    mut commands: Commands,
+    time: Res<Time>,
+    windows: Res<Windows>,
+    map: Res<Map>,
+    locked: Res<LockedDoors>,
+    fog: Res<Fog>,
+    mut minimap: ResMut<Minimap>,
+    materials: Res<MinimapMaterials>,
+    mut shown: Local<Option<bool>>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    minimap_query: Query<Entity, With<MinimapNode>>,
+) {
+    let refresh = minimap.timer.tick(time.delta()).just_finished();
+    if !refresh && !map.is_changed() && *shown == Some(minimap.overview) {
+        return;
+    }
+    *shown = Some(minimap.overview);
+
+    for entity in minimap_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let player = match player_query.single() {
+        Ok(p) => p.translation.truncate(),
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    let tiles = map.width().max(map.height()) as f32;
+    let (frame, size) = if minimap.overview {
+        let screen = windows
+            .get_primary()
+            .map_or(MINIMAP_SIZE, |w| w.width().min(w.height()));
+        let size = screen * OVERVIEW_FILL;
+        let style = Style {
+            size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+            position_type: PositionType::Absolute,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        };
+        (style, size)
+    } else {
+        let style = Style {
+            size: Size::new(Val::Px(MINIMAP_SIZE), Val::Px(MINIMAP_SIZE)),
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(30.),
+                left: Val::Px(5.),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        (style, MINIMAP_SIZE)
+    };
+    let scale = size / tiles;
+
+    // `x` and `y` in tiles, the map's y axis points up like the world's
+    let rect =
+        |x: f32, y: f32, width: f32, height: f32, material: Handle<ColorMaterial>| NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(width * scale), Val::Px(height * scale)),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(x * scale),
+                    bottom: Val::Px(y * scale),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            material,
+            ..Default::default()
+        };
+    // markers are centered on `position`, in tiles, and stay visible on small maps
+    let marker = |position: Vec2, material: Handle<ColorMaterial>| {
+        let side = scale.max(MIN_MARKER_SIZE) / scale;
+        rect(
+            position.x - side / 2.,
+            position.y - side / 2.,
+            side,
+            side,
+            material,
+        )
+    };
+
+    // the corner map only shows what the guard has found, the one on the wall shows everything
+    let tile_material = |tile: (usize, usize)| match map.occupied[tile] {
+        _ if !minimap.overview && !fog.discovered(tile) => None,
+        TileKind::Empty => None,
+        TileKind::Room(room) if map.rooms[room].0 == RoomKind::Security => {
+            Some(materials.security.clone())
+        }
+        TileKind::Room(_) => Some(materials.room.clone()),
+        TileKind::Door { id, .. } if locked.0.contains(&id) => Some(materials.door_locked.clone()),
+        TileKind::Door { .. } => Some(materials.door_open.clone()),
+        TileKind::Hallway => Some(materials.hallway.clone()),
+    };
+
+    let mut root = commands.spawn_bundle(NodeBundle {
+        style: frame,
+        material: if minimap.overview {
+            materials.shade.clone()
+        } else {
+            materials.clear.clone()
+        },
+        ..Default::default()
+    });
+    root.insert(MinimapNode).insert(NightEntity);
+
+    root.with_children(|parent| {
+        let mut map_node = parent.spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(size), Val::Px(size)),
+                ..Default::default()
+            },
+            material: materials.background.clone(),
+            ..Default::default()
+        });
+        map_node.with_children(|parent| {
+            for y in 0..map.height() {
+                let mut x = 0;
+                while x < map.width() {
+                    let material = tile_material((x, y));
+                    let start = x;
+                    while x < map.width() && tile_material((x, y)) == material {
+                        x += 1;
+                    }
+                    if let Some(material) = material {
+                        let width = (x - start) as f32;
+                        parent.spawn_bundle(rect(start as f32, y as f32, width, 1., material));
+                    }
+                }
+            }
+
+            // cameras in rooms the guard hasn't found yet would give the layout away
+            for camera in map.cameras.iter() {
+                if !minimap.overview && !fog.discovered((camera.x, camera.y)) {
+                    continue;
+                }
+                let position = Vec2::new(camera.x as f32, camera.y as f32) + Vec2::splat(0.5);
+                parent.spawn_bundle(marker(position, materials.camera.clone()));
+            }
+            for &(x, y) in minimap.last_seen.values() {
+                let position = Vec2::new(x as f32, y as f32) + Vec2::splat(0.5);
+                parent.spawn_bundle(marker(position, materials.intruder.clone()));
+            }
+            parent.spawn_bundle(marker(player / TILE_SIZE, materials.guard.clone()));
+        });
+    });
+}
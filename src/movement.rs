@@ -0,0 +1,249 @@
+use crate::collision::Collided;
+use crate::input::{Action, ActionState};
+use crate::{Player, TIMESTEP};
+use bevy::prelude::*;
+
+const STAMINA_BAR_WIDTH: f32 = 120.;
+
+/// Distance moved per tick.
+#[derive(Default, Clone, Copy)]
+pub struct Velocity(pub Vec2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gait {
+    Walk,
+    Run,
+    Sneak,
+}
+
+impl Default for Gait {
+    fn default() -> Self {
+        Gait::Walk
+    }
+}
+
+/// How loud something is being right now, from 0 (silent) to 1 (running flat out).
+#[derive(Default, Clone, Copy)]
+pub struct Noise(pub f32);
+
+/// Seconds of running the guard has left in them.
+pub struct Stamina {
+    pub current: f32,
+    /// Ran out completely and can't run again until some of it comes back.
+    pub winded: bool,
+}
+
+impl Default for Stamina {
+    fn default() -> Self {
+        Self {
+            current: MovementParams::default().stamina,
+            winded: false,
+        }
+    }
+}
+
+/// Tuning for player movement. Speeds and rates are per tick, stamina is in seconds.
+pub struct MovementParams {
+    pub walk_speed: f32,
+    pub run_speed: f32,
+    pub sneak_speed: f32,
+    /// How quickly velocity moves towards the input direction.
+    pub acceleration: f32,
+    /// How quickly velocity drops off with no input.
+    pub friction: f32,
+    pub walk_noise: f32,
+    pub run_noise: f32,
+    pub sneak_noise: f32,
+    /// Longest the guard can run for.
+    pub stamina: f32,
+    /// Seconds of stamina regained per second when not running.
+    pub stamina_regen: f32,
+    /// How much stamina has to come back after running out before running again.
+    pub winded_recovery: f32,
+}
+
+impl Default for MovementParams {
+    fn default() -> Self {
+        Self {
+            walk_speed: 2.,
+            run_speed: 3.5,
+            sneak_speed: 1.,
+            acceleration: 0.4,
+            friction: 0.3,
+            walk_noise: 0.4,
+            run_noise: 1.,
+            sneak_noise: 0.1,
+            stamina: 5.,
+            stamina_regen: 0.5,
+            winded_recovery: 2.,
+        }
+    }
+}
+
+impl MovementParams {
+    pub fn speed(&self, gait: Gait) -> f32 {
+        match gait {
+            Gait::Walk => self.walk_speed,
+            Gait::Run => self.run_speed,
+            Gait::Sneak => self.sneak_speed,
+        }
+    }
+
+    /// How much noise moving at `speed` with `gait` makes. Standing still is silent whatever the
+    /// gait.
+    pub fn noise(&self, gait: Gait, speed: f32) -> f32 {
+        let loudness = match gait {
+            Gait::Walk => self.walk_noise,
+            Gait::Run => self.run_noise,
+            Gait::Sneak => self.sneak_noise,
+        };
+        loudness * (speed / self.speed(gait)).min(1.)
+    }
+}
+
+/// Move `velocity` towards `target` by at most `rate`.
+fn approach(velocity: Vec2, target: Vec2, rate: f32) -> Vec2 {
+    let difference = target - velocity;
+    let distance = difference.length();
+    if distance <= rate {
+        target
+    } else {
+        velocity + difference / distance * rate
+    }
+}
+
+pub fn move_player(
+    params: Res<MovementParams>,
+    actions: Res<ActionState>,
+    mut query: Query<
+        (
+            &mut Transform,
+            &mut Velocity,
+            &mut Gait,
+            &mut Noise,
+            &mut Stamina,
+        ),
+        With<Player>,
+    >,
+) {
+    let (mut tran, mut velocity, mut gait, mut noise, mut stamina) = match query.single_mut() {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    *gait = if actions.pressed(Action::Sneak) {
+        Gait::Sneak
+    } else if actions.pressed(Action::Run) && !stamina.winded {
+        Gait::Run
+    } else {
+        Gait::Walk
+    };
+
+    // a full diagonal is as fast as a straight line, a half pushed stick is slower
+    let mut direction = actions.movement;
+    if direction.length() > 1. {
+        direction = direction.normalize();
+    }
+
+    velocity.0 = if direction == Vec2::ZERO {
+        approach(velocity.0, Vec2::ZERO, params.friction)
+    } else {
+        approach(
+            velocity.0,
+            direction * params.speed(*gait),
+            params.acceleration,
+        )
+    };
+
+    tran.translation.x += velocity.0.x;
+    tran.translation.y += velocity.0.y;
+    noise.0 = params.noise(*gait, velocity.0.length());
+
+    let tick = TIMESTEP as f32;
+    if *gait == Gait::Run && velocity.0 != Vec2::ZERO {
+        stamina.current = (stamina.current - tick).max(0.);
+        stamina.winded |= stamina.current == 0.;
+    } else {
+        stamina.current = (stamina.current + params.stamina_regen * tick).min(params.stamina);
+        stamina.winded &= stamina.current < params.winded_recovery;
+    }
+}
+
+struct StaminaBar;
+
+pub fn setup_stamina_bar(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(STAMINA_BAR_WIDTH), Val::Px(8.)),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(30.),
+                    left: Val::Px(5.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            material: materials.add(Color::rgba(0., 0., 0., 0.6).into()),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                        ..Default::default()
+                    },
+                    material: materials.add(Color::LIME_GREEN.into()),
+                    ..Default::default()
+                })
+                .insert(StaminaBar);
+        });
+}
+
+/// Fill the bar with what's left, red while winded.
+pub fn update_stamina_bar(
+    params: Res<MovementParams>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    player_query: Query<&Stamina, (With<Player>, Changed<Stamina>)>,
+    mut bar_query: Query<(&mut Style, &Handle<ColorMaterial>), With<StaminaBar>>,
+) {
+    let stamina = match player_query.single() {
+        Ok(s) => s,
+        // nothing to update
+        Err(_) => return,
+    };
+
+    let (mut style, material) = match bar_query.single_mut() {
+        Ok(b) => b,
+        Err(e) => {
+            error!("Stamina bar not found: {}", e);
+            return;
+        }
+    };
+
+    style.size.width = Val::Percent(100. * stamina.current / params.stamina);
+    let color = if stamina.winded {
+        Color::RED
+    } else {
+        Color::LIME_GREEN
+    };
+    if materials.get(material).map_or(false, |m| m.color != color) {
+        materials.get_mut(material).unwrap().color = color;
+    }
+}
+
+/// Stop moving into whatever was run into, keeping the part of the velocity along it.
+pub fn stop_on_collision(mut collided: EventReader<Collided>, mut query: Query<&mut Velocity>) {
+    for event in collided.iter() {
+        if let Ok(mut velocity) = query.get_mut(event.entity) {
+            let into = velocity.0.dot(event.normal);
+            if into < 0. {
+                velocity.0 -= event.normal * into;
+            }
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use crate::catch::{IntruderBrokeAway, IntruderCaught};
+use crate::intruder::*;
+use crate::mapgen::Map;
+use crate::state::{AppState, GameSettings};
+use crate::valuable::Valuables;
+use bevy::prelude::*;
+
+/// Largest map any night generates, in tiles.
+const MAX_MAP_SIZE: usize = 40;
+const MAX_INTRUDERS: usize = 6;
+const MAX_INTRUDER_SPEED: f32 = 2.5;
+const MAX_INTRUDER_SKILL: f32 = 1.5;
+/// The shift runs from 22:00 to 06:00.
+const SHIFT_START_HOUR: f32 = 22.;
+const SHIFT_HOURS: f32 = 8.;
+/// Intruders only turn up in this much of the night, so the last one still has time to do
+/// something.
+const ARRIVAL_WINDOW: f32 = 0.6;
+
+/// How tonight's shift is set up, worked out from the settings before the world is generated.
+pub struct NightPlan {
+    /// Real seconds from the start of the shift to dawn.
+    pub length: f32,
+    /// Width and height of the map in tiles.
+    pub map_size: usize,
+    pub intruders: usize,
+    pub intruder_speed: f32,
+    /// How much quicker than the first night's intruders tonight's steal, hide and flee. Fed
+    /// into the `BehaviourTable`.
+    pub intruder_skill: f32,
+}
+
+impl NightPlan {
+    /// Every night after the first builds a bigger building with more, faster and more skilled
+    /// intruders.
+    pub fn new(settings: &GameSettings) -> Self {
+        let difficulty = settings.difficulty;
+        let later = (settings.night - 1) as usize;
+        Self {
+            length: 180. + 30. * later.min(4) as f32,
+            map_size: (difficulty.map_size() + 2 * later).min(MAX_MAP_SIZE),
+            intruders: (difficulty.intruders() + later / 2).min(MAX_INTRUDERS),
+            intruder_speed: (difficulty.intruder_speed() * (1. + 0.08 * later as f32))
+                .min(MAX_INTRUDER_SPEED),
+            intruder_skill: (1. + 0.1 * later as f32).min(MAX_INTRUDER_SKILL),
+        }
+    }
+
+    /// Seconds into the night the `i`th intruder turns up. They're spread evenly over the
+    /// arrival window, the first one right at the start.
+    pub fn arrival(&self, i: usize) -> f32 {
+        // a night without intruders never asks, but shouldn't divide by zero if it does
+        self.length * ARRIVAL_WINDOW * i as f32 / self.intruders.max(1) as f32
+    }
+
+    /// Wall clock time `elapsed` seconds into the shift, as hours and minutes.
+    pub fn clock(&self, elapsed: f32) -> (u32, u32) {
+        let hours = SHIFT_START_HOUR + SHIFT_HOURS * (elapsed / self.length).min(1.);
+        let minutes = (hours * 60.) as u32;
+        (minutes / 60 % 24, minutes % 60)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NightOutcome {
+    /// Made it to dawn without anything going missing.
+    Dawn,
+    /// Every intruder was caught, counted from `IntruderCaught`. Never happens on a night
+    /// without intruders.
+    CaughtAll,
+    /// Something got stolen.
+    Robbed,
+}
+
+impl NightOutcome {
+    pub fn won(self) -> bool {
+        self != NightOutcome::Robbed
+    }
+}
+
+/// How the current night is going, and how it went once it's over.
+#[derive(Default)]
+pub struct NightReport {
+    pub elapsed: f32,
+    /// Intruders that have turned up so far.
+    pub arrived: usize,
+    pub caught: usize,
+    /// Times an intruder got out of the guard's grip.
+    pub escapes: usize,
+    pub outcome: Option<NightOutcome>,
+}
+
+pub struct IntruderMaterial(pub Handle<ColorMaterial>);
+
+impl FromWorld for IntruderMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        Self(materials.add(Color::CRIMSON.into()))
+    }
+}
+
+struct NightClockText;
+
+/// The clock goes top and center, out of the way of the door console in the top right corner.
+pub fn setup_night_clock(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Auto),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(5.),
+                    ..Default::default()
+                },
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::with_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+                            font_size: 20.,
+                            color: Color::WHITE,
+                        },
+                        Default::default(),
+                    ),
+                    ..Default::default()
+                })
+                .insert(NightClockText);
+        });
+}
+
+/// Bring in each intruder once its arrival time comes round.
+pub fn spawn_scheduled_intruders(
+    mut commands: Commands,
+    plan: Res<NightPlan>,
+    map: Res<Map>,
+    material: Res<IntruderMaterial>,
+    mut report: ResMut<NightReport>,
+) {
+    while report.arrived < plan.intruders && report.elapsed >= plan.arrival(report.arrived) {
+        spawn_intruder(&mut commands, &map, material.0.clone(), plan.intruder_speed);
+        report.arrived += 1;
+    }
+}
+
+/// Run the clock and end the night on a theft, once every intruder is caught, or at dawn.
+pub fn advance_night(
+    time: Res<Time>,
+    plan: Res<NightPlan>,
+    valuables: Res<Valuables>,
+    mut report: ResMut<NightReport>,
+    mut caught: EventReader<IntruderCaught>,
+    mut broke_away: EventReader<IntruderBrokeAway>,
+    mut state: ResMut<State<AppState>>,
+) {
+    report.elapsed += time.delta_seconds();
+    report.caught += caught.iter().count();
+    report.escapes += broke_away.iter().count();
+
+    report.outcome = if !valuables.stolen.is_empty() {
+        Some(NightOutcome::Robbed)
+    } else if plan.intruders > 0 && report.caught >= plan.intruders {
+        Some(NightOutcome::CaughtAll)
+    } else if report.elapsed >= plan.length {
+        Some(NightOutcome::Dawn)
+    } else {
+        None
+    };
+
+    if report.outcome.is_some() {
+        // several things can want to change state the same frame, the first one wins
+        let _ = state.set(AppState::NightOver);
+    }
+}
+
+pub fn update_night_clock(
+    settings: Res<GameSettings>,
+    plan: Res<NightPlan>,
+    report: Res<NightReport>,
+    mut text_query: Query<&mut Text, With<NightClockText>>,
+) {
+    let mut text = match text_query.single_mut() {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Night clock text not found: {}", e);
+            return;
+        }
+    };
+
+    let (hours, minutes) = plan.clock(report.elapsed);
+    let value = format!(
+        "Night {}  {:02}:{:02}  Caught {}/{}",
+        settings.night, hours, minutes, report.caught, plan.intruders
+    );
+    if text.sections[0].value != value {
+        text.sections[0].value = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Difficulty;
+
+    fn plan(difficulty: Difficulty, night: u32) -> NightPlan {
+        NightPlan::new(&GameSettings {
+            seed: 0,
+            difficulty,
+            night,
+        })
+    }
+
+    #[test]
+    fn first_night_matches_the_difficulty() {
+        for &difficulty in [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard].iter() {
+            let first = plan(difficulty, 1);
+            assert_eq!(first.length, 180.);
+            assert_eq!(first.map_size, difficulty.map_size());
+            assert_eq!(first.intruders, difficulty.intruders());
+            assert_eq!(first.intruder_speed, difficulty.intruder_speed());
+            assert_eq!(first.intruder_skill, 1.);
+        }
+    }
+
+    #[test]
+    fn later_nights_get_harder() {
+        let nights = (1..=20)
+            .map(|night| plan(Difficulty::Normal, night))
+            .collect::<Vec<_>>();
+        for pair in nights.windows(2) {
+            let (before, after) = (&pair[0], &pair[1]);
+            assert!(after.length >= before.length);
+            assert!(after.map_size >= before.map_size);
+            assert!(after.intruders >= before.intruders);
+            assert!(after.intruder_speed >= before.intruder_speed);
+            assert!(after.intruder_skill >= before.intruder_skill);
+        }
+
+        let third = &nights[2];
+        assert_eq!(third.length, 240.);
+        assert_eq!(third.map_size, 24);
+        assert_eq!(third.intruders, 2);
+        assert!((third.intruder_speed - 1.5 * 1.16).abs() < 1e-5);
+        assert!((third.intruder_skill - 1.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ramp_stops_at_the_limits() {
+        let last = plan(Difficulty::Hard, 100);
+        assert_eq!(last.length, 300.);
+        assert_eq!(last.map_size, MAX_MAP_SIZE);
+        assert_eq!(last.intruders, MAX_INTRUDERS);
+        assert_eq!(last.intruder_speed, MAX_INTRUDER_SPEED);
+        assert_eq!(last.intruder_skill, MAX_INTRUDER_SKILL);
+    }
+
+    #[test]
+    fn arrivals_are_spread_over_the_window() {
+        let mut plan = plan(Difficulty::Normal, 1);
+        plan.length = 100.;
+        plan.intruders = 4;
+        let arrivals = (0..4).map(|i| plan.arrival(i)).collect::<Vec<_>>();
+        for (arrival, expected) in arrivals.iter().zip([0., 15., 30., 45.].iter()) {
+            assert!(
+                (arrival - expected).abs() < 1e-4,
+                "{} is not {}",
+                arrival,
+                expected
+            );
+        }
+        // the last one still turns up before the window closes
+        assert!(arrivals[3] < plan.length * ARRIVAL_WINDOW);
+    }
+
+    #[test]
+    fn arrival_without_intruders() {
+        let mut plan = plan(Difficulty::Normal, 1);
+        plan.intruders = 0;
+        assert_eq!(plan.arrival(0), 0.);
+        assert!(plan.arrival(1).is_finite());
+    }
+}
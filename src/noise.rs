@@ -0,0 +1,253 @@
+use crate::behaviour::Senses;
+use crate::door::*;
+use crate::mapgen::Map;
+use crate::movement::Noise;
+use crate::room::*;
+use crate::trap::{TrapKind, TrapTriggered};
+use crate::Player;
+use bevy::prelude::*;
+
+/// How many tiles a noise at full loudness carries through open space.
+const HEARING_RANGE: f32 = 10.;
+/// How many extra tiles of distance it takes to get through a closed door.
+const CLOSED_DOOR_COST: f32 = 3.;
+const LOCKED_DOOR_COST: f32 = 4.;
+/// Seconds between footstep noises.
+const FOOTSTEP_INTERVAL: f32 = 0.4;
+/// Anything quieter than this by the time it arrives goes unnoticed.
+const HEARING_THRESHOLD: f32 = 0.05;
+/// How long a noise cue stays up around the guard.
+const CUE_SECONDS: f32 = 0.75;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseKind {
+    Footsteps,
+    Door,
+    Trap,
+}
+
+pub struct NoiseEmitted {
+    /// Whatever made the noise, so it doesn't hear itself.
+    pub source: Option<Entity>,
+    pub position: Vec2,
+    /// From 0 to 1.
+    pub loudness: f32,
+    pub kind: NoiseKind,
+}
+
+pub struct NoiseHeard {
+    pub listener: Entity,
+    pub kind: NoiseKind,
+    /// How loud it was by the time it got there.
+    pub level: f32,
+    /// The way the sound came in from, which is through the nearest doorway rather than straight
+    /// through the wall.
+    pub direction: Vec2,
+}
+
+/// Something that can hear noises.
+pub struct Listener;
+
+pub struct NoiseCueMaterial(Handle<ColorMaterial>);
+
+impl FromWorld for NoiseCueMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        Self(materials.add(Color::rgba(1.0, 1.0, 1.0, 0.8).into()))
+    }
+}
+
+struct NoiseCue(Timer);
+
+/// Footsteps from anything making noise, at a steady rate while it's moving.
+pub fn emit_footsteps(
+    time: Res<Time>,
+    mut elapsed: Local<f32>,
+    query: Query<(Entity, &GlobalTransform, &Noise)>,
+    mut noises: EventWriter<NoiseEmitted>,
+) {
+    *elapsed += time.delta_seconds();
+    if *elapsed < FOOTSTEP_INTERVAL {
+        return;
+    }
+    *elapsed = 0.;
+
+    for (entity, tran, noise) in query.iter() {
+        if noise.0 > 0. {
+            noises.send(NoiseEmitted {
+                source: Some(entity),
+                position: tran.translation.truncate(),
+                loudness: noise.0,
+                kind: NoiseKind::Footsteps,
+            });
+        }
+    }
+}
+
+pub fn emit_door_noise(
+    map: Res<Map>,
+    mut changes: EventReader<DoorChanged>,
+    mut noises: EventWriter<NoiseEmitted>,
+) {
+    for change in changes.iter() {
+        let door = match map.door(change.id) {
+            Some(door) => door,
+            None => continue,
+        };
+        // bolts are quieter than the door swinging
+        let loudness = if change.from == DoorState::Locked || change.to == DoorState::Locked {
+            0.3
+        } else {
+            0.6
+        };
+        // whoever worked the door doesn't get scared by it
+        noises.send(NoiseEmitted {
+            source: change.by,
+            position: tile_to_world(door.x, door.y),
+            loudness,
+            kind: NoiseKind::Door,
+        });
+    }
+}
+
+pub fn emit_trap_noise(
+    mut triggered: EventReader<TrapTriggered>,
+    mut noises: EventWriter<NoiseEmitted>,
+) {
+    for event in triggered.iter() {
+        let loudness = match event.kind {
+            TrapKind::NoiseMaker => 1.,
+            TrapKind::Tripwire => 0.5,
+            TrapKind::StickyFloor => 0.2,
+        };
+        noises.send(NoiseEmitted {
+            source: Some(event.intruder),
+            position: tile_to_world(event.tile.0, event.tile.1),
+            loudness,
+            kind: NoiseKind::Trap,
+        });
+    }
+}
+
+/// Spread every noise through the building and tell whoever is close enough that they heard it.
+pub fn propagate_noise(
+    map: Res<Map>,
+    doors: Res<DoorEntities>,
+    door_query: Query<&Door>,
+    listener_query: Query<(Entity, &GlobalTransform), With<Listener>>,
+    mut noises: EventReader<NoiseEmitted>,
+    mut heard: EventWriter<NoiseHeard>,
+) {
+    let door_cost = |id: usize| match doors.0.get(id).and_then(|&e| door_query.get(e).ok()) {
+        Some(door) => match door.state {
+            DoorState::Open => 0.,
+            DoorState::Closed => CLOSED_DOOR_COST,
+            DoorState::Locked => LOCKED_DOOR_COST,
+        },
+        None => 0.,
+    };
+
+    for noise in noises.iter() {
+        let from = match map.world_to_tile(noise.position.x, noise.position.y) {
+            Some(tile) => tile,
+            None => continue,
+        };
+        let range = noise.loudness * HEARING_RANGE;
+        let distances = map.sound_distances(from, range, door_cost);
+
+        for (listener, tran) in listener_query.iter() {
+            if noise.source == Some(listener) {
+                continue;
+            }
+
+            let position = tran.translation.truncate();
+            let tile = match map.world_to_tile(position.x, position.y) {
+                Some(tile) => tile,
+                None => continue,
+            };
+            let distance = match distances.get(&tile) {
+                Some(&distance) => distance,
+                None => continue,
+            };
+
+            let level = noise.loudness * (1. - distance / range);
+            if level < HEARING_THRESHOLD {
+                continue;
+            }
+
+            // the sound comes in from whichever neighbour it reached first
+            let toward = map
+                .connected_neighbors(tile)
+                .into_iter()
+                .filter_map(|next| distances.get(&next).map(|&d| (next, d)))
+                .filter(|&(_, d)| d < distance)
+                .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+                .map_or(noise.position, |((x, y), _)| tile_to_world(x, y));
+
+            heard.send(NoiseHeard {
+                listener,
+                kind: noise.kind,
+                level,
+                direction: (toward - position).normalize_or_zero(),
+            });
+        }
+    }
+}
+
+/// Intruders hide from footsteps and doors banging, but not the traps they set off themselves.
+pub fn intruders_hear(
+    mut heard: EventReader<NoiseHeard>,
+    mut query: Query<(&GlobalTransform, &mut Senses)>,
+) {
+    for event in heard.iter() {
+        if event.kind == NoiseKind::Trap {
+            continue;
+        }
+        if let Ok((tran, mut senses)) = query.get_mut(event.listener) {
+            senses.heard = Some(tran.translation.truncate() + event.direction * TILE_SIZE);
+            senses.new_sound = true;
+        }
+    }
+}
+
+/// Flash a mark on the side of the guard the sound came from, bigger the louder it was.
+pub fn show_noise_cues(
+    mut commands: Commands,
+    material: Res<NoiseCueMaterial>,
+    mut heard: EventReader<NoiseHeard>,
+    player_query: Query<Entity, With<Player>>,
+) {
+    for event in heard.iter() {
+        if player_query.get(event.listener).is_err() || event.direction == Vec2::ZERO {
+            continue;
+        }
+
+        let offset = event.direction * 24.;
+        let mut transform = Transform::from_xyz(offset.x, offset.y, 2.);
+        transform.rotate(Quat::from_rotation_z(
+            event.direction.y.atan2(event.direction.x),
+        ));
+        commands.entity(event.listener).with_children(|parent| {
+            parent
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(3., 6. + event.level * 18.)),
+                    material: material.0.clone(),
+                    transform,
+                    ..Default::default()
+                })
+                .insert(NoiseCue(Timer::from_seconds(CUE_SECONDS, false)));
+        });
+    }
+}
+
+pub fn fade_noise_cues(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut NoiseCue)>,
+) {
+    for (entity, mut cue) in query.iter_mut() {
+        if cue.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
@@ -1,18 +1,35 @@
+use crate::furniture::*;
+use crate::state::NightEntity;
 use crate::Collider;
 use bevy::prelude::*;
 use itertools::Itertools;
+use rand::Rng;
 use std::collections::HashMap;
 
 pub const TILE_SIZE: f32 = 64.;
 
-#[derive(Eq, PartialEq, Hash)]
+/// Half the size of the biggest thing that walks around the map, the guard.
+const WALKER_HALF_SIZE: f32 = 8.;
+
+/// Center of a map tile in world coordinates.
+pub fn tile_to_world(x: usize, y: usize) -> Vec2 {
+    Vec2::new(
+        x as f32 * TILE_SIZE + TILE_SIZE / 2.,
+        y as f32 * TILE_SIZE + TILE_SIZE / 2.,
+    )
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum RoomKind {
     Security,
+    /// An office.
     Empty,
+    ServerRoom,
+    Vault,
     Hallway(HallwayKind),
 }
 
-#[derive(Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum HallwayKind {
     NorthEastSouthWest,
     NorthEastSouth,
@@ -39,6 +56,26 @@ pub enum Direction {
     West,
 }
 
+impl Direction {
+    pub fn opposite(self) -> Self {
+        match self {
+            Direction::North => Direction::South,
+            Direction::East => Direction::West,
+            Direction::South => Direction::North,
+            Direction::West => Direction::East,
+        }
+    }
+
+    pub fn to_vec2(self) -> Vec2 {
+        match self {
+            Direction::North => Vec2::Y,
+            Direction::East => Vec2::X,
+            Direction::South => -Vec2::Y,
+            Direction::West => -Vec2::X,
+        }
+    }
+}
+
 pub struct Room {
     pub asset: Handle<ColorMaterial>,
     pub width: usize,
@@ -46,6 +83,7 @@ pub struct Room {
     pub rotation: f32,
     pub colliders: Vec<Collider>,
     pub doors: Vec<(usize, usize, Direction)>,
+    pub furniture: Vec<FurnitureSlot>,
 }
 
 impl Room {
@@ -205,9 +243,65 @@ impl Room {
             rotation: 0.,
             colliders,
             doors,
+            furniture: Vec::new(),
         }
     }
-    pub fn spawn(&self, commands: &mut Commands, x: usize, y: usize) {
+
+    pub fn with_furniture(mut self, furniture: Vec<FurnitureSlot>) -> Self {
+        self.furniture = furniture;
+        self
+    }
+
+    /// Tiles of this room, placed at `x`, `y`, where furniture that might go in it would get in
+    /// the way of anything standing in the middle of the tile.
+    pub fn blocked_tiles(
+        &self,
+        furniture: &HashMap<FurnitureKind, Furniture>,
+        x: usize,
+        y: usize,
+    ) -> Vec<(usize, usize)> {
+        let center = tile_to_world(x, y) - Vec2::splat(TILE_SIZE / 2.)
+            + Vec2::new(self.width as f32, self.height as f32) * TILE_SIZE / 2.;
+        let rotation = Quat::from_rotation_z(self.rotation);
+        let boxes = self
+            .furniture
+            .iter()
+            .flat_map(|slot| {
+                slot.allowed
+                    .iter()
+                    .filter_map(move |kind| furniture[kind].collider)
+                    .map(move |collider| {
+                        // the same box `furnish` gives the spawned furniture
+                        let collider = collider.rotated(slot.rotation);
+                        let position = slot.position.extend(0.) + collider.offset;
+                        (
+                            center + rotation.mul_vec3(position).truncate(),
+                            collider.rotated(self.rotation).size / 2.,
+                        )
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        (x..x + self.width)
+            .cartesian_product(y..y + self.height)
+            .filter(|&(tx, ty)| {
+                let tile = tile_to_world(tx, ty);
+                boxes.iter().any(|&(center, half)| {
+                    let overlap = half + Vec2::splat(WALKER_HALF_SIZE) - (tile - center).abs();
+                    overlap.x > 0. && overlap.y > 0.
+                })
+            })
+            .collect()
+    }
+
+    pub fn spawn<R: Rng>(
+        &self,
+        commands: &mut Commands,
+        furniture: &HashMap<FurnitureKind, Furniture>,
+        rng: &mut R,
+        x: usize,
+        y: usize,
+    ) {
         // convert map coord to bevy coord
         let x = x as f32 * TILE_SIZE + self.width as f32 * TILE_SIZE / 2.;
         let y = y as f32 * TILE_SIZE + self.height as f32 * TILE_SIZE / 2.;
@@ -223,6 +317,7 @@ impl Room {
             transform,
             ..Default::default()
         });
+        entity_commands.insert(NightEntity);
 
         self.colliders.iter().for_each(|c| {
             entity_commands.with_children(|parent| {
@@ -233,6 +328,10 @@ impl Room {
                     .insert(c.clone());
             });
         });
+
+        entity_commands.with_children(|parent| {
+            furnish(parent, &transform, &self.furniture, furniture, rng);
+        });
     }
 }
 
@@ -241,65 +340,75 @@ pub fn load_rooms(
     mut materials: ResMut<Assets<ColorMaterial>>,
     mut rooms: ResMut<HashMap<RoomKind, Room>>,
 ) {
+    *rooms = new_rooms(|path| materials.add(asset_server.load(path).into()));
+}
+
+/// Build every room kind, using `material` to turn an asset path into the room's material.
+pub fn new_rooms(
+    mut material: impl FnMut(&str) -> Handle<ColorMaterial>,
+) -> HashMap<RoomKind, Room> {
     use self::Direction::*;
+    let mut rooms = HashMap::new();
     rooms.insert(
         RoomKind::Security,
         Room::new(
-            materials.add(asset_server.load("rooms/security.png").into()),
+            material("rooms/security.png"),
             3,
             3,
             vec![(1, Direction::West), (1, Direction::East)],
-        ),
+        )
+        .with_furniture(security_room_slots()),
     );
     rooms.insert(
         RoomKind::Empty,
         Room::new(
-            materials.add(asset_server.load("rooms/empty.png").into()),
+            material("rooms/empty.png"),
             4,
             3,
             vec![(1, Direction::South), (1, Direction::West)],
-        ),
+        )
+        .with_furniture(office_slots()),
     );
     rooms.insert(
-        RoomKind::Hallway(HallwayKind::North),
+        RoomKind::ServerRoom,
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/one/N.png").into()),
-            1,
-            1,
-            vec![(0, North)],
-        ),
+            material("rooms/empty.png"),
+            4,
+            3,
+            vec![(1, Direction::South), (1, Direction::West)],
+        )
+        .with_furniture(server_room_slots()),
     );
     rooms.insert(
-        RoomKind::Hallway(HallwayKind::West),
+        RoomKind::Vault,
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/one/W.png").into()),
-            1,
-            1,
-            vec![(0, West)],
-        ),
+            material("rooms/empty.png"),
+            4,
+            3,
+            vec![(1, Direction::South), (1, Direction::West)],
+        )
+        .with_furniture(vault_slots()),
+    );
+    rooms.insert(
+        RoomKind::Hallway(HallwayKind::North),
+        Room::new(material("rooms/hallways/one/N.png"), 1, 1, vec![(0, North)]),
+    );
+    rooms.insert(
+        RoomKind::Hallway(HallwayKind::West),
+        Room::new(material("rooms/hallways/one/W.png"), 1, 1, vec![(0, West)]),
     );
     rooms.insert(
         RoomKind::Hallway(HallwayKind::South),
-        Room::new(
-            materials.add(asset_server.load("rooms/hallways/one/S.png").into()),
-            1,
-            1,
-            vec![(0, South)],
-        ),
+        Room::new(material("rooms/hallways/one/S.png"), 1, 1, vec![(0, South)]),
     );
     rooms.insert(
         RoomKind::Hallway(HallwayKind::East),
-        Room::new(
-            materials.add(asset_server.load("rooms/hallways/one/E.png").into()),
-            1,
-            1,
-            vec![(0, East)],
-        ),
+        Room::new(material("rooms/hallways/one/E.png"), 1, 1, vec![(0, East)]),
     );
     rooms.insert(
         RoomKind::Hallway(HallwayKind::NorthSouth),
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/straight/NS.png").into()),
+            material("rooms/hallways/straight/NS.png"),
             1,
             1,
             vec![(0, North), (0, South)],
@@ -308,7 +417,7 @@ pub fn load_rooms(
     rooms.insert(
         RoomKind::Hallway(HallwayKind::EastWest),
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/straight/EW.png").into()),
+            material("rooms/hallways/straight/EW.png"),
             1,
             1,
             vec![(0, East), (0, West)],
@@ -317,7 +426,7 @@ pub fn load_rooms(
     rooms.insert(
         RoomKind::Hallway(HallwayKind::NorthEast),
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/angle/NE.png").into()),
+            material("rooms/hallways/angle/NE.png"),
             1,
             1,
             vec![(0, North), (0, East)],
@@ -326,7 +435,7 @@ pub fn load_rooms(
     rooms.insert(
         RoomKind::Hallway(HallwayKind::NorthWest),
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/angle/NW.png").into()),
+            material("rooms/hallways/angle/NW.png"),
             1,
             1,
             vec![(0, North), (0, West)],
@@ -335,7 +444,7 @@ pub fn load_rooms(
     rooms.insert(
         RoomKind::Hallway(HallwayKind::SouthWest),
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/angle/SW.png").into()),
+            material("rooms/hallways/angle/SW.png"),
             1,
             1,
             vec![(0, South), (0, West)],
@@ -344,7 +453,7 @@ pub fn load_rooms(
     rooms.insert(
         RoomKind::Hallway(HallwayKind::EastSouth),
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/angle/ES.png").into()),
+            material("rooms/hallways/angle/ES.png"),
             1,
             1,
             vec![(0, East), (0, South)],
@@ -353,7 +462,7 @@ pub fn load_rooms(
     rooms.insert(
         RoomKind::Hallway(HallwayKind::NorthEastWest),
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/tee/NEW.png").into()),
+            material("rooms/hallways/tee/NEW.png"),
             1,
             1,
             vec![(0, North), (0, East), (0, West)],
@@ -362,7 +471,7 @@ pub fn load_rooms(
     rooms.insert(
         RoomKind::Hallway(HallwayKind::NorthSouthWest),
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/tee/NSW.png").into()),
+            material("rooms/hallways/tee/NSW.png"),
             1,
             1,
             vec![(0, North), (0, South), (0, West)],
@@ -371,7 +480,7 @@ pub fn load_rooms(
     rooms.insert(
         RoomKind::Hallway(HallwayKind::EastSouthWest),
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/tee/ESW.png").into()),
+            material("rooms/hallways/tee/ESW.png"),
             1,
             1,
             vec![(0, East), (0, South), (0, West)],
@@ -380,7 +489,7 @@ pub fn load_rooms(
     rooms.insert(
         RoomKind::Hallway(HallwayKind::NorthEastSouth),
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/tee/NES.png").into()),
+            material("rooms/hallways/tee/NES.png"),
             1,
             1,
             vec![(0, North), (0, East), (0, South)],
@@ -389,10 +498,11 @@ pub fn load_rooms(
     rooms.insert(
         RoomKind::Hallway(HallwayKind::NorthEastSouthWest),
         Room::new(
-            materials.add(asset_server.load("rooms/hallways/four.png").into()),
+            material("rooms/hallways/four.png"),
             1,
             1,
             vec![(0, North), (0, East), (0, South), (0, West)],
         ),
     );
+    rooms
 }
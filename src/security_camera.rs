@@ -0,0 +1,246 @@
+use crate::furniture::*;
+use crate::input::{Action, ActionState};
+use crate::mapgen::{Map, TileKind};
+use crate::room::*;
+use crate::state::NightEntity;
+use crate::trigger::*;
+use crate::vision::VisionCone;
+use crate::{MainCamera, Player};
+use bevy::prelude::*;
+use bevy::render::camera::ActiveCameras;
+use bevy::render::render_graph::base::camera::CAMERA_2D;
+
+/// How far a security camera can see.
+const CAMERA_RANGE: f32 = 6. * TILE_SIZE;
+
+pub struct SecurityCamera {
+    pub id: usize,
+    /// Radians counterclockwise from east.
+    pub facing: f32,
+    pub fov: f32,
+    pub range: f32,
+}
+
+impl SecurityCamera {
+    pub fn direction(&self) -> Vec2 {
+        Vec2::new(self.facing.cos(), self.facing.sin())
+    }
+
+    pub fn cone(&self) -> VisionCone {
+        VisionCone {
+            direction: self.direction(),
+            fov: self.fov,
+            range: self.range,
+        }
+    }
+}
+
+/// Camera entities indexed by camera id.
+#[derive(Default)]
+pub struct SecurityCameras(pub Vec<Entity>);
+
+/// Monitor mode at the security desk. `ToggleMonitor` switches to the camera feeds,
+/// `PrevFeed`/`NextFeed` cycle through them.
+#[derive(Default)]
+pub struct Monitor {
+    pub active: bool,
+    pub feed: usize,
+}
+
+/// The second 2d camera, looking down the selected feed. It shares the main camera's name so it
+/// renders through the same pass, `monitor_view` swaps which of the two is active.
+pub struct MonitorCamera;
+
+struct MonitorText;
+
+pub fn spawn_cameras(
+    commands: &mut Commands,
+    map: &Map,
+    material: Handle<ColorMaterial>,
+) -> SecurityCameras {
+    let cameras = map
+        .cameras
+        .iter()
+        .enumerate()
+        .map(|(id, placed)| {
+            let camera = SecurityCamera {
+                id,
+                facing: placed.facing,
+                fov: placed.fov,
+                range: CAMERA_RANGE,
+            };
+
+            // cameras in rooms are tucked into the corner they look out of
+            let mut position = tile_to_world(placed.x, placed.y);
+            if let TileKind::Room(_) = map.occupied[(placed.x, placed.y)] {
+                position -= camera.direction() * (TILE_SIZE / 2. - 10.) * std::f32::consts::SQRT_2;
+            }
+
+            let mut transform = Transform::from_xyz(position.x, position.y, 0.6);
+            transform.rotate(Quat::from_rotation_z(camera.facing));
+            let view = TriggerVolume::new(
+                TriggerKind::CameraView(id),
+                TriggerShape::Cone(camera.cone()),
+            );
+            commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(12., 8.)),
+                    material: material.clone(),
+                    transform,
+                    ..Default::default()
+                })
+                .insert(camera)
+                .insert(view)
+                .insert(NightEntity)
+                .id()
+        })
+        .collect();
+
+    SecurityCameras(cameras)
+}
+
+pub fn setup_monitor(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(5.),
+                    left: Val::Px(5.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+                    font_size: 24.,
+                    color: Color::RED,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(MonitorText);
+}
+
+pub fn monitor_input(
+    actions: Res<ActionState>,
+    mut monitor: ResMut<Monitor>,
+    cameras: Res<SecurityCameras>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    desk_query: Query<(&GlobalTransform, &FurnitureKind)>,
+) {
+    let player = match player_query.single() {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    if !at_security_desk(player.translation, desk_query.iter()) {
+        if monitor.active {
+            monitor.active = false;
+        }
+        return;
+    }
+
+    if actions.just_pressed(Action::ToggleMonitor) {
+        monitor.active = !monitor.active;
+    }
+    if !monitor.active || cameras.0.is_empty() {
+        return;
+    }
+
+    let count = cameras.0.len();
+    if actions.just_pressed(Action::NextFeed) {
+        monitor.feed = (monitor.feed + 1) % count;
+    } else if actions.just_pressed(Action::PrevFeed) {
+        monitor.feed = (monitor.feed + count - 1) % count;
+    }
+}
+
+/// Point the monitor camera down the selected feed, and render through it instead of the main
+/// camera while the monitor is on. This Bevy has no viewports, so the feed fills the screen, but
+/// the main camera stays on the guard for when they look up.
+pub fn monitor_view(
+    monitor: Res<Monitor>,
+    cameras: Res<SecurityCameras>,
+    mut active_cameras: ResMut<ActiveCameras>,
+    camera_query: Query<(&GlobalTransform, &SecurityCamera)>,
+    main_camera_query: Query<Entity, With<MainCamera>>,
+    mut monitor_camera_query: Query<(Entity, &mut Transform), With<MonitorCamera>>,
+) {
+    let main_camera = match main_camera_query.single() {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Main Camera not found: {}", e);
+            return;
+        }
+    };
+
+    let (monitor_camera, mut monitor_tran) = match monitor_camera_query.single_mut() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Monitor Camera not found: {}", e);
+            return;
+        }
+    };
+
+    let rendering = if monitor.active {
+        monitor_camera
+    } else {
+        main_camera
+    };
+    // both cameras are called `CAMERA_2D`, so bevy would otherwise pick one of them by itself
+    let swapped = active_cameras
+        .get(CAMERA_2D)
+        .map_or(false, |active| active.entity != Some(rendering));
+    if swapped {
+        if let Some(active) = active_cameras.get_mut(CAMERA_2D) {
+            active.entity = Some(rendering);
+        }
+    }
+
+    if !monitor.active {
+        return;
+    }
+
+    let (tran, camera) = match cameras.0.get(monitor.feed).map(|&e| camera_query.get(e)) {
+        Some(Ok(c)) => c,
+        _ => {
+            error!("Security camera {} not found", monitor.feed);
+            return;
+        }
+    };
+
+    let center = tran.translation.truncate() + camera.direction() * camera.range / 2.;
+    monitor_tran.translation.x = center.x;
+    monitor_tran.translation.y = center.y;
+}
+
+pub fn update_monitor_text(
+    monitor: Res<Monitor>,
+    cameras: Res<SecurityCameras>,
+    mut text_query: Query<&mut Text, With<MonitorText>>,
+) {
+    if !monitor.is_changed() {
+        return;
+    }
+
+    let mut text = match text_query.single_mut() {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Monitor text not found: {}", e);
+            return;
+        }
+    };
+
+    text.sections[0].value = if monitor.active {
+        format!("CAM {:02}/{:02}", monitor.feed + 1, cameras.0.len())
+    } else {
+        String::new()
+    };
+}
@@ -0,0 +1,373 @@
+use crate::input::RebindScreen;
+use crate::mapgen::Map;
+use crate::night::{NightOutcome, NightReport};
+use crate::valuable::Valuables;
+use bevy::app::AppExit;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    MainMenu,
+    /// Picking the seed and difficulty for a new game.
+    Setup,
+    Playing,
+    /// Pushed on top of `Playing`, so the night is still there underneath.
+    Paused,
+    NightOver,
+}
+
+/// Sets how hard the first night is. `NightPlan` ramps it up from there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn next(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Easy,
+        }
+    }
+
+    pub fn prev(self) -> Self {
+        self.next().next()
+    }
+
+    /// Width and height of the map in tiles.
+    pub fn map_size(self) -> usize {
+        match self {
+            Difficulty::Easy => 16,
+            Difficulty::Normal => 20,
+            Difficulty::Hard => 26,
+        }
+    }
+
+    pub fn intruders(self) -> usize {
+        match self {
+            Difficulty::Easy | Difficulty::Normal => 1,
+            Difficulty::Hard => 2,
+        }
+    }
+
+    pub fn intruder_speed(self) -> f32 {
+        match self {
+            Difficulty::Easy => 1.2,
+            Difficulty::Normal => 1.5,
+            Difficulty::Hard => 1.8,
+        }
+    }
+}
+
+/// What the current game was started with.
+pub struct GameSettings {
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    /// Starts at 1.
+    pub night: u32,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            seed: rand::thread_rng().gen(),
+            difficulty: Difficulty::Normal,
+            night: 1,
+        }
+    }
+}
+
+impl GameSettings {
+    /// Seed for the night being played, so every night of a game gets a different building but
+    /// the same seed always gives the same game.
+    pub fn night_seed(&self) -> u64 {
+        self.seed.wrapping_add(u64::from(self.night - 1))
+    }
+}
+
+/// Belongs to the night being played and gets despawned when it ends.
+pub struct NightEntity;
+
+/// Root of whatever menu or overlay the current state is showing.
+struct Screen;
+struct ScreenText;
+
+/// Run criteria for gameplay on the fixed timestep, which only ticks while a night is being
+/// played.
+pub fn playing(
+    In(input): In<ShouldRun>,
+    state: Res<State<AppState>>,
+    map: Option<Res<Map>>,
+) -> ShouldRun {
+    // every night's world is still being spawned on the tick the state changes, and
+    // `teardown_night` takes the last one's map away so nothing runs against the old building
+    if *state.current() == AppState::Playing && map.is_some() {
+        input
+    } else {
+        ShouldRun::No
+    }
+}
+
+fn spawn_screen(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    materials: &mut Assets<ColorMaterial>,
+    background: Color,
+) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(background.into()),
+            ..Default::default()
+        })
+        .insert(Screen)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::with_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+                            font_size: 24.,
+                            color: Color::WHITE,
+                        },
+                        Default::default(),
+                    ),
+                    ..Default::default()
+                })
+                .insert(ScreenText);
+        });
+}
+
+/// A screen covering everything behind it.
+pub fn open_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    spawn_screen(&mut commands, &asset_server, &mut materials, Color::BLACK);
+}
+
+/// A screen the night still shows through.
+pub fn open_overlay(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    spawn_screen(
+        &mut commands,
+        &asset_server,
+        &mut materials,
+        Color::rgba(0., 0., 0., 0.6),
+    );
+}
+
+pub fn close_screen(mut commands: Commands, query: Query<Entity, With<Screen>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn set_screen_text(query: &mut Query<&mut Text, With<ScreenText>>, value: String) {
+    let mut text = match query.single_mut() {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Screen text not found: {}", e);
+            return;
+        }
+    };
+    if text.sections[0].value != value {
+        text.sections[0].value = value;
+    }
+}
+
+/// Enter starts setting up a new game, Escape quits.
+pub fn main_menu(
+    mut keys: ResMut<Input<KeyCode>>,
+    rebind: Res<RebindScreen>,
+    mut state: ResMut<State<AppState>>,
+    mut exit: EventWriter<AppExit>,
+    mut text_query: Query<&mut Text, With<ScreenText>>,
+) {
+    set_screen_text(
+        &mut text_query,
+        String::from("ILLUSION OF SECURITY\n\nEnter  new game\nF5     controls\nEsc    quit"),
+    );
+    if rebind.open {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Return) {
+        // the next screen would see the same press otherwise
+        keys.reset(KeyCode::Return);
+        state.set(AppState::Setup).unwrap();
+    } else if keys.just_pressed(KeyCode::Escape) {
+        exit.send(AppExit);
+    }
+}
+
+/// Up/Down pick the seed or difficulty, Left/Right change it, R rolls a random seed, Enter starts
+/// the first night and Escape goes back.
+pub fn setup_menu(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut cursor: Local<usize>,
+    mut settings: ResMut<GameSettings>,
+    mut state: ResMut<State<AppState>>,
+    mut text_query: Query<&mut Text, With<ScreenText>>,
+) {
+    if keys.just_pressed(KeyCode::Up) || keys.just_pressed(KeyCode::Down) {
+        *cursor = 1 - *cursor;
+    } else if keys.just_pressed(KeyCode::Left) {
+        match *cursor {
+            0 => settings.seed = settings.seed.wrapping_sub(1),
+            _ => settings.difficulty = settings.difficulty.prev(),
+        }
+    } else if keys.just_pressed(KeyCode::Right) {
+        match *cursor {
+            0 => settings.seed = settings.seed.wrapping_add(1),
+            _ => settings.difficulty = settings.difficulty.next(),
+        }
+    } else if keys.just_pressed(KeyCode::R) {
+        settings.seed = rand::thread_rng().gen();
+    } else if keys.just_pressed(KeyCode::Return) {
+        keys.reset(KeyCode::Return);
+        settings.night = 1;
+        state.set(AppState::Playing).unwrap();
+    } else if keys.just_pressed(KeyCode::Escape) {
+        keys.reset(KeyCode::Escape);
+        state.set(AppState::MainMenu).unwrap();
+    }
+
+    let rows = [
+        format!("Seed        {}", settings.seed),
+        format!("Difficulty  {:?}", settings.difficulty),
+    ];
+    let mut value = String::from("NEW GAME\n\n");
+    for (i, row) in rows.iter().enumerate() {
+        let marker = if i == *cursor { ">" } else { " " };
+        value += &format!("{} {}\n", marker, row);
+    }
+    value += "\nR  random seed\nEnter  start\nEsc  back";
+    set_screen_text(&mut text_query, value);
+}
+
+/// Escape pauses the night.
+pub fn pause_input(mut keys: ResMut<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+    if keys.just_pressed(KeyCode::Escape) {
+        keys.reset(KeyCode::Escape);
+        let _ = state.push(AppState::Paused);
+    }
+}
+
+/// Escape goes back to the night, Q abandons it for the main menu.
+pub fn paused_menu(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<AppState>>,
+    mut text_query: Query<&mut Text, With<ScreenText>>,
+) {
+    set_screen_text(
+        &mut text_query,
+        String::from("PAUSED\n\nEsc  resume\nQ    quit to menu"),
+    );
+
+    if keys.just_pressed(KeyCode::Escape) {
+        keys.reset(KeyCode::Escape);
+        state.pop().unwrap();
+    } else if keys.just_pressed(KeyCode::Q) {
+        state.replace(AppState::MainMenu).unwrap();
+    }
+}
+
+/// Despawn everything the night spawned.
+pub fn teardown_night(mut commands: Commands, query: Query<Entity, With<NightEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    // the rest of the night's resources are replaced by `generate_world`, but until then
+    // `playing` keeps the fixed timestep off for as long as there's no map
+    commands.remove_resource::<Map>();
+}
+
+/// Enter moves on to the next night, or tries the same one again after a loss. Escape goes back
+/// to the main menu.
+pub fn night_over_menu(
+    mut keys: ResMut<Input<KeyCode>>,
+    report: Res<NightReport>,
+    valuables: Res<Valuables>,
+    mut settings: ResMut<GameSettings>,
+    mut state: ResMut<State<AppState>>,
+    mut text_query: Query<&mut Text, With<ScreenText>>,
+) {
+    let won = report.outcome.map_or(false, NightOutcome::won);
+    let headline = match report.outcome {
+        Some(NightOutcome::Dawn) => "MADE IT TO DAWN",
+        Some(NightOutcome::CaughtAll) => "CAUGHT THEM ALL",
+        Some(NightOutcome::Robbed) => "ROBBED",
+        None => "NIGHT OVER",
+    };
+    let minutes = report.elapsed as u32 / 60;
+    let seconds = report.elapsed as u32 % 60;
+    set_screen_text(
+        &mut text_query,
+        format!(
+            "NIGHT {}: {}\n\nLasted      {}:{:02}\nIntruders   {}\nCaught      {}\nBroke away  {}\nStolen      {} ({} of {})\n\nEnter  {}\nEsc    main menu",
+            settings.night,
+            headline,
+            minutes,
+            seconds,
+            report.arrived,
+            report.caught,
+            report.escapes,
+            valuables.stolen.len(),
+            valuables.stolen_value(),
+            valuables.total_value(),
+            if won { "next night" } else { "try again" },
+        ),
+    );
+
+    if keys.just_pressed(KeyCode::Return) {
+        keys.reset(KeyCode::Return);
+        if won {
+            settings.night += 1;
+        }
+        state.set(AppState::Playing).unwrap();
+    } else if keys.just_pressed(KeyCode::Escape) {
+        keys.reset(KeyCode::Escape);
+        state.set(AppState::MainMenu).unwrap();
+    }
+}
+
+/// Screens and moving between them. Gameplay systems gate themselves on `AppState::Playing`.
+pub struct GameStatePlugin;
+
+impl Plugin for GameStatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state(AppState::MainMenu)
+            .init_resource::<GameSettings>()
+            .add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(open_screen))
+            .add_system_set(SystemSet::on_update(AppState::MainMenu).with_system(main_menu))
+            .add_system_set(SystemSet::on_exit(AppState::MainMenu).with_system(close_screen))
+            .add_system_set(SystemSet::on_enter(AppState::Setup).with_system(open_screen))
+            .add_system_set(SystemSet::on_update(AppState::Setup).with_system(setup_menu))
+            .add_system_set(SystemSet::on_exit(AppState::Setup).with_system(close_screen))
+            .add_system_set(SystemSet::on_update(AppState::Playing).with_system(pause_input))
+            .add_system_set(SystemSet::on_exit(AppState::Playing).with_system(teardown_night))
+            .add_system_set(SystemSet::on_enter(AppState::Paused).with_system(open_overlay))
+            .add_system_set(SystemSet::on_update(AppState::Paused).with_system(paused_menu))
+            .add_system_set(SystemSet::on_exit(AppState::Paused).with_system(close_screen))
+            .add_system_set(SystemSet::on_enter(AppState::NightOver).with_system(open_screen))
+            .add_system_set(SystemSet::on_update(AppState::NightOver).with_system(night_over_menu))
+            .add_system_set(SystemSet::on_exit(AppState::NightOver).with_system(close_screen));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::input::{Action, ActionState};
+use crate::mapgen::{Map, TileKind};
+use crate::room::*;
+use crate::security_camera::Monitor;
+use crate::trap::Revealed;
+use crate::{Nonstatic, Player};
+use bevy::prelude::*;
+use rand::Rng;
+use std::collections::HashMap;
+
+/// Width and height of the radar on screen, in pixels.
+const RADAR_SIZE: f32 = 160.;
+const PING_SIZE: f32 = 6.;
+/// How far something has to move between sweeps to show up.
+const MOTION_THRESHOLD: f32 = 1.;
+
+pub struct TrackerSettings {
+    /// Seconds between sweeps.
+    pub interval: f32,
+    pub range: f32,
+    /// Chance of a ghost ping showing up on each sweep.
+    pub false_positives: f64,
+}
+
+impl Default for TrackerSettings {
+    fn default() -> Self {
+        Self {
+            interval: 1.5,
+            range: 8. * TILE_SIZE,
+            false_positives: 0.2,
+        }
+    }
+}
+
+/// Something the tracker picked up, snapped to the map tile it was on.
+pub struct Ping {
+    pub tile: (usize, usize),
+    /// False positives don't correspond to anything.
+    pub real: bool,
+}
+
+/// The guard's handheld motion tracker. `ToggleTracker` raises and lowers it.
+pub struct MotionTracker {
+    pub settings: TrackerSettings,
+    pub raised: bool,
+    pub pings: Vec<Ping>,
+    last_seen: HashMap<Entity, Vec2>,
+}
+
+impl Default for MotionTracker {
+    fn default() -> Self {
+        Self::new(TrackerSettings::default())
+    }
+}
+
+impl MotionTracker {
+    pub fn new(settings: TrackerSettings) -> Self {
+        Self {
+            settings,
+            raised: false,
+            pings: Vec::new(),
+            last_seen: HashMap::new(),
+        }
+    }
+}
+
+pub struct TrackerMaterials {
+    radar: Handle<ColorMaterial>,
+    ping: Handle<ColorMaterial>,
+    center: Handle<ColorMaterial>,
+}
+
+impl FromWorld for TrackerMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        Self {
+            radar: materials.add(Color::rgba(0.0, 0.15, 0.0, 0.8).into()),
+            ping: materials.add(Color::rgb(0.3, 1.0, 0.3).into()),
+            center: materials.add(Color::WHITE.into()),
+        }
+    }
+}
+
+struct TrackerRadar;
+
+pub fn tracker_input(
+    actions: Res<ActionState>,
+    monitor: Res<Monitor>,
+    mut tracker: ResMut<MotionTracker>,
+) {
+    // hands are busy at the monitor
+    if monitor.active {
+        if tracker.raised {
+            tracker.raised = false;
+        }
+        return;
+    }
+
+    if actions.just_pressed(Action::ToggleTracker) {
+        tracker.raised = !tracker.raised;
+        tracker.pings.clear();
+        tracker.last_seen.clear();
+    }
+}
+
+/// Every interval, ping whatever moved since the last sweep within range of the guard. The tracker
+/// is only touched mutably when a sweep lands, so the radar isn't redrawn every frame.
+pub fn sweep_tracker(
+    time: Res<Time>,
+    mut elapsed: Local<f32>,
+    map: Res<Map>,
+    mut tracker: ResMut<MotionTracker>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    moving_query: Query<
+        (Entity, &GlobalTransform, Option<&Revealed>),
+        (With<Nonstatic>, Without<Player>),
+    >,
+) {
+    if !tracker.raised {
+        *elapsed = 0.;
+        return;
+    }
+    *elapsed += time.delta_seconds();
+    if *elapsed < tracker.settings.interval {
+        return;
+    }
+    *elapsed = 0.;
+
+    let player = match player_query.single() {
+        Ok(p) => p.translation.truncate(),
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    let range = tracker.settings.range;
+    let mut pings = Vec::new();
+    let mut last_seen = HashMap::new();
+    for (entity, tran, revealed) in moving_query.iter() {
+        let position = tran.translation.truncate();
+        let moved = tracker
+            .last_seen
+            .get(&entity)
+            .map_or(false, |last| last.distance(position) > MOTION_THRESHOLD);
+        // revealed intruders show up wherever they are, moving or not
+        if revealed.is_some() || (moved && position.distance(player) <= range) {
+            if let Some(tile) = map.world_to_tile(position.x, position.y) {
+                pings.push(Ping { tile, real: true });
+            }
+        }
+        last_seen.insert(entity, position);
+    }
+
+    // ghost pings land on some walkable tile in range
+    let mut rng = rand::thread_rng();
+    if rng.gen_bool(tracker.settings.false_positives) {
+        let offset = Vec2::new(rng.gen_range(-range..range), rng.gen_range(-range..range));
+        let ghost = player + offset;
+        if let Some(tile) = map.world_to_tile(ghost.x, ghost.y) {
+            if map.occupied[tile] != TileKind::Empty && ghost.distance(player) <= range {
+                pings.push(Ping { tile, real: false });
+            }
+        }
+    }
+
+    tracker.pings = pings;
+    tracker.last_seen = last_seen;
+}
+
+/// Redraw the radar in the bottom right corner whenever the tracker changes.
+pub fn render_tracker(
+    mut commands: Commands,
+    tracker: Res<MotionTracker>,
+    materials: Res<TrackerMaterials>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    radar_query: Query<Entity, With<TrackerRadar>>,
+) {
+    if !tracker.is_changed() {
+        return;
+    }
+
+    for entity in radar_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    if !tracker.raised {
+        return;
+    }
+
+    let player = match player_query.single() {
+        Ok(p) => p.translation.truncate(),
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    let scale = RADAR_SIZE / 2. / tracker.settings.range;
+    let blip = |offset: Vec2, material: Handle<ColorMaterial>| NodeBundle {
+        style: Style {
+            size: Size::new(Val::Px(PING_SIZE), Val::Px(PING_SIZE)),
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Px(RADAR_SIZE / 2. + offset.x - PING_SIZE / 2.),
+                bottom: Val::Px(RADAR_SIZE / 2. + offset.y - PING_SIZE / 2.),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        material,
+        ..Default::default()
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(RADAR_SIZE), Val::Px(RADAR_SIZE)),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(5.),
+                    right: Val::Px(5.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            material: materials.radar.clone(),
+            ..Default::default()
+        })
+        .insert(TrackerRadar)
+        .with_children(|parent| {
+            parent.spawn_bundle(blip(Vec2::ZERO, materials.center.clone()));
+            for ping in tracker.pings.iter() {
+                let mut offset = (tile_to_world(ping.tile.0, ping.tile.1) - player) * scale;
+                // revealed intruders can be out of range, pin them to the edge
+                let edge = RADAR_SIZE / 2. - PING_SIZE / 2.;
+                if offset.abs().max_element() > edge {
+                    offset *= edge / offset.abs().max_element();
+                }
+                parent.spawn_bundle(blip(offset, materials.ping.clone()));
+            }
+        });
+}
@@ -0,0 +1,313 @@
+use crate::input::{Action, ActionState};
+use crate::intruder::Intruder;
+use crate::mapgen::{Map, TileKind};
+use crate::room::*;
+use crate::state::NightEntity;
+use crate::trigger::*;
+use crate::{Collider, Player};
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+/// How long a slowed intruder stays slowed.
+const SLOW_SECONDS: f32 = 4.;
+/// How much a slowed intruder's speed is multiplied by.
+pub const SLOW_FACTOR: f32 = 0.4;
+/// How long a revealed intruder stays revealed.
+const REVEAL_SECONDS: f32 = 6.;
+/// How long an alarm stays on screen.
+const ALARM_SECONDS: f32 = 4.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrapKind {
+    NoiseMaker,
+    Tripwire,
+    StickyFloor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapEffect {
+    /// Tell the guard where the trap went off.
+    Alarm,
+    /// Slow the intruder down for a while.
+    Slow,
+    /// Show the intruder on the motion tracker wherever it is.
+    Reveal,
+}
+
+impl TrapKind {
+    pub const ALL: [TrapKind; 3] = [
+        TrapKind::NoiseMaker,
+        TrapKind::Tripwire,
+        TrapKind::StickyFloor,
+    ];
+
+    pub fn effect(self) -> TrapEffect {
+        match self {
+            TrapKind::NoiseMaker => TrapEffect::Reveal,
+            TrapKind::Tripwire => TrapEffect::Alarm,
+            TrapKind::StickyFloor => TrapEffect::Slow,
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            TrapKind::NoiseMaker => Color::ORANGE,
+            TrapKind::Tripwire => Color::YELLOW,
+            TrapKind::StickyFloor => Color::PURPLE,
+        }
+    }
+}
+
+pub struct Trap {
+    pub kind: TrapKind,
+    pub tile: (usize, usize),
+}
+
+/// Traps the guard has left to place. The select actions pick a kind and `PlaceTrap` places one
+/// on the current tile.
+pub struct TrapInventory {
+    pub counts: HashMap<TrapKind, usize>,
+    pub selected: TrapKind,
+}
+
+impl Default for TrapInventory {
+    fn default() -> Self {
+        Self {
+            counts: TrapKind::ALL.iter().map(|&kind| (kind, 2)).collect(),
+            selected: TrapKind::NoiseMaker,
+        }
+    }
+}
+
+pub struct TrapTriggered {
+    pub trap: Entity,
+    pub kind: TrapKind,
+    pub intruder: Entity,
+    pub tile: (usize, usize),
+}
+
+pub struct Slowed(pub Timer);
+pub struct Revealed(pub Timer);
+
+/// The last alarm to go off, shown until its timer runs out.
+#[derive(Default)]
+pub struct Alarm {
+    pub tile: Option<(usize, usize)>,
+    timer: Timer,
+}
+
+struct TrapText;
+
+/// Whether a trap can go on `tile`. Traps go on hallway and room floors, but not in doorways or
+/// the security room.
+pub fn valid_trap_tile(map: &Map, tile: (usize, usize)) -> bool {
+    match map.occupied[tile] {
+        TileKind::Hallway => true,
+        TileKind::Room(id) => map.rooms[id].0 != RoomKind::Security,
+        TileKind::Empty | TileKind::Door { .. } => false,
+    }
+}
+
+pub fn setup_traps(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(5.),
+                    left: Val::Px(200.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+                    font_size: 18.,
+                    color: Color::ORANGE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(TrapText);
+}
+
+pub fn place_traps(
+    mut commands: Commands,
+    actions: Res<ActionState>,
+    map: Res<Map>,
+    mut inventory: ResMut<TrapInventory>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    trap_query: Query<&Trap>,
+) {
+    let selects = [
+        Action::SelectNoiseMaker,
+        Action::SelectTripwire,
+        Action::SelectStickyFloor,
+    ];
+    for (&select, &kind) in selects.iter().zip(TrapKind::ALL.iter()) {
+        if actions.just_pressed(select) {
+            inventory.selected = kind;
+        }
+    }
+
+    if !actions.just_pressed(Action::PlaceTrap) {
+        return;
+    }
+
+    let player = match player_query.single() {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Player entity not found: {}", e);
+            return;
+        }
+    };
+
+    let tile = match map.world_to_tile(player.translation.x, player.translation.y) {
+        Some(tile) => tile,
+        None => return,
+    };
+    if !valid_trap_tile(&map, tile) || trap_query.iter().any(|trap| trap.tile == tile) {
+        return;
+    }
+
+    let kind = inventory.selected;
+    match inventory.counts.get_mut(&kind) {
+        Some(count) if *count > 0 => *count -= 1,
+        _ => return,
+    }
+
+    let position = tile_to_world(tile.0, tile.1);
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite::new(Vec2::new(12., 12.)),
+            material: materials.add(kind.color().into()),
+            transform: Transform::from_xyz(position.x, position.y, 0.4),
+            ..Default::default()
+        })
+        .insert(Trap { kind, tile })
+        .insert(TriggerVolume::new(
+            TriggerKind::Trap,
+            TriggerShape::Box(Collider::new(Vec2::splat(TILE_SIZE), Vec2::ZERO)),
+        ))
+        .insert(NightEntity);
+}
+
+/// Set off any trap an intruder walks onto. Traps only go off once.
+pub fn trigger_traps(
+    mut commands: Commands,
+    mut entered: EventReader<TriggerEntered>,
+    trap_query: Query<&Trap>,
+    intruder_query: Query<(), With<Intruder>>,
+    mut triggered: EventWriter<TrapTriggered>,
+) {
+    for event in entered.iter() {
+        if event.kind != TriggerKind::Trap || intruder_query.get(event.entity).is_err() {
+            continue;
+        }
+        if let Ok(&Trap { kind, tile }) = trap_query.get(event.trigger) {
+            commands.entity(event.trigger).despawn();
+            triggered.send(TrapTriggered {
+                trap: event.trigger,
+                kind,
+                intruder: event.entity,
+                tile,
+            });
+        }
+    }
+}
+
+pub fn apply_trap_effects(
+    mut commands: Commands,
+    mut triggered: EventReader<TrapTriggered>,
+    mut alarm: ResMut<Alarm>,
+) {
+    for event in triggered.iter() {
+        match event.kind.effect() {
+            TrapEffect::Alarm => {
+                alarm.tile = Some(event.tile);
+                alarm.timer = Timer::from_seconds(ALARM_SECONDS, false);
+            }
+            TrapEffect::Slow => {
+                commands
+                    .entity(event.intruder)
+                    .insert(Slowed(Timer::from_seconds(SLOW_SECONDS, false)));
+            }
+            TrapEffect::Reveal => {
+                commands
+                    .entity(event.intruder)
+                    .insert(Revealed(Timer::from_seconds(REVEAL_SECONDS, false)));
+            }
+        }
+    }
+}
+
+/// Count down trap effects and clear them once they wear off.
+pub fn expire_trap_effects(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut alarm: ResMut<Alarm>,
+    mut slowed_query: Query<(Entity, &mut Slowed)>,
+    mut revealed_query: Query<(Entity, &mut Revealed)>,
+) {
+    let delta = time.delta();
+    for (entity, mut slowed) in slowed_query.iter_mut() {
+        if slowed.0.tick(delta).finished() {
+            commands.entity(entity).remove::<Slowed>();
+        }
+    }
+    for (entity, mut revealed) in revealed_query.iter_mut() {
+        if revealed.0.tick(delta).finished() {
+            commands.entity(entity).remove::<Revealed>();
+        }
+    }
+    if alarm.tile.is_some() && alarm.timer.tick(delta).finished() {
+        alarm.tile = None;
+    }
+}
+
+pub fn update_trap_text(
+    inventory: Res<TrapInventory>,
+    alarm: Res<Alarm>,
+    map: Res<Map>,
+    mut text_query: Query<&mut Text, With<TrapText>>,
+) {
+    if !inventory.is_changed() && !alarm.is_changed() {
+        return;
+    }
+
+    let mut text = match text_query.single_mut() {
+        Ok(t) => t,
+        Err(e) => {
+            error!("Trap text not found: {}", e);
+            return;
+        }
+    };
+
+    let mut value = TrapKind::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, kind)| {
+            let cursor = if *kind == inventory.selected {
+                ">"
+            } else {
+                " "
+            };
+            let count = inventory.counts.get(kind).copied().unwrap_or(0);
+            format!("{}{} {:?} x{}", cursor, i + 1, kind, count)
+        })
+        .collect::<Vec<_>>()
+        .join("  ");
+
+    if let Some((x, y)) = alarm.tile {
+        let location = match map.occupied[(x, y)] {
+            TileKind::Room(id) => format!("{:?} room {}", map.rooms[id].0, id),
+            _ => format!("hallway {},{}", x, y),
+        };
+        value.push_str(&format!("\nALARM: tripwire in {}", location));
+    }
+    text.sections[0].value = value;
+}
@@ -0,0 +1,137 @@
+use crate::collision::Aabb;
+use crate::mapgen::Map;
+use crate::room::*;
+use crate::state::NightEntity;
+use crate::vision::VisionCone;
+use crate::{Collider, Nonstatic};
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+/// What a trigger volume stands for, so listeners can tell "entered a room" from "walked past a
+/// camera".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TriggerKind {
+    /// Index into `Map::rooms`.
+    Room(usize),
+    /// Door id.
+    Door(usize),
+    Trap,
+    /// Security camera id.
+    CameraView(usize),
+}
+
+#[derive(Clone, Copy)]
+pub enum TriggerShape {
+    /// A box the same shape a `Collider` would be, without blocking anything.
+    Box(Collider),
+    /// Anything whose center is inside the cone.
+    Cone(VisionCone),
+}
+
+/// An area that reports nonstatic colliders going in and out of it.
+pub struct TriggerVolume {
+    pub kind: TriggerKind,
+    pub shape: TriggerShape,
+}
+
+impl TriggerVolume {
+    pub fn new(kind: TriggerKind, shape: TriggerShape) -> Self {
+        Self { kind, shape }
+    }
+
+    fn contains(&self, global: &GlobalTransform, local: &Transform, other: &Aabb) -> bool {
+        match self.shape {
+            TriggerShape::Box(collider) => Aabb::of(global, local, &collider).overlaps(other),
+            TriggerShape::Cone(cone) => cone.contains(global.translation.truncate(), other.center),
+        }
+    }
+}
+
+pub struct TriggerEntered {
+    pub trigger: Entity,
+    pub kind: TriggerKind,
+    pub entity: Entity,
+}
+
+pub struct TriggerExited {
+    pub trigger: Entity,
+    pub kind: TriggerKind,
+    pub entity: Entity,
+}
+
+/// Which nonstatic entities are inside which trigger volumes.
+#[derive(Default)]
+pub struct TriggerOverlaps(pub HashMap<(Entity, Entity), TriggerKind>);
+
+impl TriggerOverlaps {
+    /// Every trigger `entity` is currently inside.
+    pub fn inside(&self, entity: Entity) -> impl Iterator<Item = (Entity, TriggerKind)> + '_ {
+        self.0
+            .iter()
+            .filter(move |((_, e), _)| *e == entity)
+            .map(|(&(trigger, _), &kind)| (trigger, kind))
+    }
+}
+
+/// Spawn a trigger volume covering every room on the map.
+pub fn spawn_room_triggers(commands: &mut Commands, map: &Map, rooms: &HashMap<RoomKind, Room>) {
+    for (id, (kind, (x, y))) in map.rooms.iter().enumerate() {
+        let room = &rooms[kind];
+        let size = Vec2::new(room.width as f32, room.height as f32) * TILE_SIZE;
+        let center = Vec2::new(*x as f32, *y as f32) * TILE_SIZE + size / 2.;
+        let transform = Transform::from_xyz(center.x, center.y, 0.);
+        commands.spawn_bundle((
+            transform,
+            GlobalTransform::from(transform),
+            TriggerVolume::new(
+                TriggerKind::Room(id),
+                TriggerShape::Box(Collider::new(size, Vec2::ZERO)),
+            ),
+            NightEntity,
+        ));
+    }
+}
+
+pub fn detect_triggers(
+    mut overlaps: ResMut<TriggerOverlaps>,
+    trigger_query: Query<(Entity, &GlobalTransform, &Transform, &TriggerVolume)>,
+    moving_query: Query<(Entity, &GlobalTransform, &Transform, &Collider), With<Nonstatic>>,
+    mut entered: EventWriter<TriggerEntered>,
+    mut exited: EventWriter<TriggerExited>,
+) {
+    let movers = moving_query
+        .iter()
+        .map(|(entity, global, local, collider)| (entity, Aabb::of(global, local, collider)))
+        .collect::<Vec<_>>();
+
+    let mut current = HashMap::new();
+    for (trigger, global, local, volume) in trigger_query.iter() {
+        for (entity, aabb) in movers.iter() {
+            if *entity != trigger && volume.contains(global, local, aabb) {
+                current.insert((trigger, *entity), volume.kind);
+            }
+        }
+    }
+
+    for (&(trigger, entity), &kind) in current.iter() {
+        if !overlaps.0.contains_key(&(trigger, entity)) {
+            entered.send(TriggerEntered {
+                trigger,
+                kind,
+                entity,
+            });
+        }
+    }
+    // despawned triggers and movers count as leaving too
+    for (&(trigger, entity), &kind) in overlaps.0.iter() {
+        if !current.contains_key(&(trigger, entity)) {
+            exited.send(TriggerExited {
+                trigger,
+                kind,
+                entity,
+            });
+        }
+    }
+
+    overlaps.0 = current;
+}
@@ -0,0 +1,99 @@
+use crate::behaviour::IntruderStole;
+use crate::furniture::FurnitureKind;
+use crate::mapgen::Map;
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Something worth stealing, placed by the room's furniture.
+#[derive(Debug, Clone, Copy)]
+pub struct Valuable {
+    pub kind: FurnitureKind,
+    pub value: u32,
+    /// Where it is in the world. Valuables never move, so this is worked out once when the room
+    /// is furnished.
+    pub position: Vec2,
+}
+
+impl Valuable {
+    pub fn tile(&self, map: &Map) -> Option<(usize, usize)> {
+        map.world_to_tile(self.position.x, self.position.y)
+    }
+}
+
+/// Every valuable in tonight's building, and what's been taken.
+#[derive(Default)]
+pub struct Valuables {
+    pub remaining: Vec<(Entity, Valuable)>,
+    pub stolen: Vec<Valuable>,
+}
+
+impl Valuables {
+    /// What everything in the building was worth at the start of the night.
+    pub fn total_value(&self) -> u32 {
+        self.remaining_value() + self.stolen_value()
+    }
+
+    pub fn remaining_value(&self) -> u32 {
+        self.remaining
+            .iter()
+            .map(|(_, valuable)| valuable.value)
+            .sum()
+    }
+
+    pub fn stolen_value(&self) -> u32 {
+        self.stolen.iter().map(|valuable| valuable.value).sum()
+    }
+
+    /// A valuable left in the building for an intruder to go after, favouring the ones worth
+    /// more.
+    pub fn pick<R: Rng>(&self, rng: &mut R) -> Option<&Valuable> {
+        self.remaining
+            .choose_weighted(rng, |(_, valuable)| valuable.value)
+            .ok()
+            .map(|(_, valuable)| valuable)
+    }
+}
+
+pub struct ValuableStolen {
+    pub intruder: Entity,
+    pub valuable: Valuable,
+}
+
+pub fn register_valuables(
+    mut valuables: ResMut<Valuables>,
+    query: Query<(Entity, &Valuable), Added<Valuable>>,
+) {
+    for (entity, valuable) in query.iter() {
+        valuables.remaining.push((entity, *valuable));
+    }
+}
+
+/// Take whatever's on the tile an intruder finished stealing from. Two intruders can go for the
+/// same thing, the second one just finds it gone.
+pub fn steal_valuables(
+    mut commands: Commands,
+    map: Res<Map>,
+    mut valuables: ResMut<Valuables>,
+    mut stole: EventReader<IntruderStole>,
+    mut stolen: EventWriter<ValuableStolen>,
+) {
+    for event in stole.iter() {
+        let index = match valuables
+            .remaining
+            .iter()
+            .position(|(_, valuable)| valuable.tile(&map) == Some(event.tile))
+        {
+            Some(index) => index,
+            None => continue,
+        };
+
+        let (entity, valuable) = valuables.remaining.remove(index);
+        commands.entity(entity).despawn_recursive();
+        valuables.stolen.push(valuable);
+        stolen.send(ValuableStolen {
+            intruder: event.intruder,
+            valuable,
+        });
+    }
+}
@@ -0,0 +1,184 @@
+use crate::collision::Aabb;
+use crate::door::Door;
+use crate::input::{Action, ActionState};
+use crate::security_camera::SecurityCamera;
+use crate::{Collider, Nonstatic};
+use bevy::prelude::*;
+use ncollide2d::na::{Isometry2, Point2, Vector2};
+use ncollide2d::query::{Ray, RayCast};
+use ncollide2d::shape::Cuboid;
+
+/// Distance between the dots drawn along each ray of the debug overlay.
+const DEBUG_DOT_SPACING: f32 = 16.;
+/// Rays drawn across each vision cone in the debug overlay.
+const DEBUG_RAYS: usize = 12;
+
+/// A static collider that blocks sight.
+pub struct Occluder {
+    aabb: Aabb,
+    shape: Cuboid<f32>,
+}
+
+impl Occluder {
+    pub fn new(aabb: Aabb) -> Self {
+        Self {
+            aabb,
+            shape: Cuboid::new(Vector2::new(aabb.half.x, aabb.half.y)),
+        }
+    }
+
+    fn toi(&self, ray: &Ray<f32>, max_toi: f32) -> Option<f32> {
+        let center = self.aabb.center;
+        let isometry = Isometry2::translation(center.x, center.y);
+        self.shape.toi_with_ray(&isometry, ray, max_toi, true)
+    }
+}
+
+/// Every wall, closed door and piece of furniture, rebuilt each tick so doors opening and closing
+/// change what can be seen.
+#[derive(Default)]
+pub struct Occluders(pub Vec<Occluder>);
+
+impl Occluders {
+    /// How far a ray from `origin` along `direction` gets before hitting something, up to
+    /// `max_distance`. Occluders the ray starts inside are ignored so cameras mounted against a
+    /// wall can still see out of it.
+    pub fn cast(&self, origin: Vec2, direction: Vec2, max_distance: f32) -> f32 {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec2::ZERO {
+            return 0.;
+        }
+
+        let ray = Ray::new(
+            Point2::new(origin.x, origin.y),
+            Vector2::new(direction.x, direction.y),
+        );
+        self.0
+            .iter()
+            .filter(|occluder| !occluder.aabb.contains(origin))
+            .filter_map(|occluder| occluder.toi(&ray, max_distance))
+            .fold(max_distance, f32::min)
+    }
+
+    /// Whether nothing blocks the straight line between `from` and `to`.
+    pub fn line_of_sight(&self, from: Vec2, to: Vec2) -> bool {
+        let distance = from.distance(to);
+        self.cast(from, to - from, distance) >= distance
+    }
+}
+
+/// What something can see: `fov` radians around `direction`, out to `range`.
+#[derive(Clone, Copy)]
+pub struct VisionCone {
+    pub direction: Vec2,
+    pub fov: f32,
+    pub range: f32,
+}
+
+impl VisionCone {
+    /// A cone that sees all the way around.
+    pub fn all_around(range: f32) -> Self {
+        Self {
+            direction: Vec2::X,
+            fov: std::f32::consts::TAU,
+            range,
+        }
+    }
+
+    /// Whether `target` is within range and inside the cone, ignoring walls.
+    pub fn contains(&self, origin: Vec2, target: Vec2) -> bool {
+        let to_target = target - origin;
+        let distance = to_target.length();
+        distance <= self.range
+            && (distance == 0. || self.direction.angle_between(to_target).abs() <= self.fov / 2.)
+    }
+
+    /// Whether `target` is inside the cone and not hidden behind anything.
+    pub fn can_see(&self, origin: Vec2, target: Vec2, occluders: &Occluders) -> bool {
+        self.contains(origin, target) && occluders.line_of_sight(origin, target)
+    }
+
+    /// Directions evenly spread across the cone, edge to edge.
+    fn rays(&self, count: usize) -> impl Iterator<Item = Vec2> {
+        let start = self.direction.y.atan2(self.direction.x) - self.fov / 2.;
+        let step = self.fov / (count - 1) as f32;
+        (0..count).map(move |i| {
+            let angle = start + step * i as f32;
+            Vec2::new(angle.cos(), angle.sin())
+        })
+    }
+}
+
+pub fn update_occluders(
+    mut occluders: ResMut<Occluders>,
+    query: Query<(&GlobalTransform, &Transform, &Collider, Option<&Door>), Without<Nonstatic>>,
+) {
+    occluders.0 = query
+        .iter()
+        .filter(|(_, _, _, door)| !door.map_or(false, Door::is_open))
+        .map(|(global, local, collider, _)| Occluder::new(Aabb::of(global, local, collider)))
+        .collect();
+}
+
+/// Draws what every security camera can see. F3 toggles.
+#[derive(Default)]
+pub struct VisionDebug(pub bool);
+
+pub struct VisionDebugMaterial(Handle<ColorMaterial>);
+
+impl FromWorld for VisionDebugMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        Self(materials.add(Color::rgba(1.0, 1.0, 0.3, 0.5).into()))
+    }
+}
+
+struct VisionDebugDot;
+
+pub fn vision_debug_input(actions: Res<ActionState>, mut debug: ResMut<VisionDebug>) {
+    if actions.just_pressed(Action::VisionDebug) {
+        debug.0 = !debug.0;
+    }
+}
+
+pub fn vision_debug_render(
+    mut commands: Commands,
+    debug: Res<VisionDebug>,
+    occluders: Res<Occluders>,
+    material: Res<VisionDebugMaterial>,
+    camera_query: Query<(&GlobalTransform, &SecurityCamera)>,
+    dot_query: Query<Entity, With<VisionDebugDot>>,
+) {
+    // doors change what the cameras see, so the overlay is redrawn every frame it's up
+    if !debug.0 && !debug.is_changed() {
+        return;
+    }
+
+    for entity in dot_query.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    if !debug.0 {
+        return;
+    }
+
+    for (tran, camera) in camera_query.iter() {
+        let origin = tran.translation.truncate();
+        let cone = camera.cone();
+        for direction in cone.rays(DEBUG_RAYS) {
+            let reach = occluders.cast(origin, direction, cone.range);
+            let dots = (reach / DEBUG_DOT_SPACING) as usize;
+            for i in 1..=dots {
+                let position = origin + direction * DEBUG_DOT_SPACING * i as f32;
+                commands
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite::new(Vec2::new(3., 3.)),
+                        material: material.0.clone(),
+                        transform: Transform::from_xyz(position.x, position.y, 9.),
+                        ..Default::default()
+                    })
+                    .insert(VisionDebugDot);
+            }
+        }
+    }
+}